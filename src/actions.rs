@@ -110,6 +110,43 @@ pub fn unlock<S: std::hash::BuildHasher>(
         Err(e) => return Err(e),
     };
 
+    unlock_with_key(key, protected_private_key, protected_org_keys)
+}
+
+pub fn unlock_with_pin<S: std::hash::BuildHasher>(
+    email: &str,
+    pin: &crate::pin::Pin,
+    password: &crate::locked::Password,
+    kdf: crate::api::KdfType,
+    iterations: u32,
+    memory: Option<u32>,
+    parallelism: Option<u32>,
+    protected_private_key: &str,
+    protected_org_keys: &std::collections::HashMap<String, String, S>,
+) -> Result<(
+    crate::locked::Keys,
+    std::collections::HashMap<String, crate::locked::Keys>,
+)> {
+    let key = pin.decrypt_key(
+        email,
+        password,
+        kdf,
+        iterations,
+        memory,
+        parallelism,
+    )?;
+
+    unlock_with_key(key, protected_private_key, protected_org_keys)
+}
+
+pub fn unlock_with_key<S: std::hash::BuildHasher>(
+    key: crate::locked::Keys,
+    protected_private_key: &str,
+    protected_org_keys: &std::collections::HashMap<String, String, S>,
+) -> Result<(
+    crate::locked::Keys,
+    std::collections::HashMap<String, crate::locked::Keys>,
+)> {
     let protected_private_key =
         crate::cipherstring::CipherString::new(protected_private_key)?;
     let private_key =
@@ -204,6 +241,7 @@ pub fn edit(
     notes: Option<&str>,
     folder_uuid: Option<&str>,
     history: &[crate::db::HistoryEntry],
+    revision_date: Option<&str>,
 ) -> Result<(Option<String>, ())> {
     with_exchange_refresh_token(access_token, refresh_token, |access_token| {
         edit_once(
@@ -216,6 +254,7 @@ pub fn edit(
             notes,
             folder_uuid,
             history,
+            revision_date,
         )
     })
 }
@@ -230,6 +269,7 @@ fn edit_once(
     notes: Option<&str>,
     folder_uuid: Option<&str>,
     history: &[crate::db::HistoryEntry],
+    revision_date: Option<&str>,
 ) -> Result<()> {
     let (client, _) = api_client()?;
     client.edit(
@@ -242,6 +282,7 @@ fn edit_once(
         notes,
         folder_uuid,
         history,
+        revision_date,
     )?;
     Ok(())
 }
@@ -428,6 +428,8 @@ struct SyncResCipher {
     key: Option<String>,
     #[serde(rename = "Reprompt", alias = "reprompt")]
     reprompt: CipherRepromptType,
+    #[serde(rename = "RevisionDate", alias = "revisionDate")]
+    revision_date: Option<String>,
 }
 
 impl SyncResCipher {
@@ -551,6 +553,7 @@ impl SyncResCipher {
             history,
             key: self.key.clone(),
             master_password_reprompt: self.reprompt,
+            revision_date: self.revision_date.clone(),
         })
     }
 }
@@ -760,6 +763,18 @@ struct CiphersPostReq {
     identity: Option<CipherIdentity>,
     #[serde(rename = "secureNote")]
     secure_note: Option<CipherSecureNote>,
+    #[serde(rename = "sshKey")]
+    ssh_key: Option<CiphersReqSshKey>,
+}
+
+#[derive(serde::Serialize, Debug)]
+struct CiphersReqSshKey {
+    #[serde(rename = "privateKey")]
+    private_key: Option<String>,
+    #[serde(rename = "publicKey")]
+    public_key: Option<String>,
+    #[serde(rename = "keyFingerprint")]
+    fingerprint: Option<String>,
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -780,6 +795,10 @@ struct CiphersPutReq {
     secure_note: Option<CipherSecureNote>,
     #[serde(rename = "passwordHistory")]
     password_history: Vec<CiphersPutReqHistory>,
+    // the server refuses the edit if the cipher has been modified since
+    // this time
+    #[serde(rename = "lastKnownRevisionDate")]
+    last_known_revision_date: Option<String>,
 }
 
 #[derive(serde::Serialize, Debug)]
@@ -1203,7 +1222,13 @@ impl Client {
         folder_id: Option<&str>,
     ) -> Result<()> {
         let mut req = CiphersPostReq {
-            ty: 1,
+            ty: match data {
+                crate::db::EntryData::Login { .. } => 1,
+                crate::db::EntryData::SecureNote => 2,
+                crate::db::EntryData::Card { .. } => 3,
+                crate::db::EntryData::Identity { .. } => 4,
+                crate::db::EntryData::SshKey { .. } => 5,
+            },
             folder_id: folder_id.map(std::string::ToString::to_string),
             name: name.to_string(),
             notes: notes.map(std::string::ToString::to_string),
@@ -1211,6 +1236,7 @@ impl Client {
             card: None,
             identity: None,
             secure_note: None,
+            ssh_key: None,
         };
         match data {
             crate::db::EntryData::Login {
@@ -1297,7 +1323,17 @@ impl Client {
             crate::db::EntryData::SecureNote => {
                 req.secure_note = Some(CipherSecureNote {});
             }
-            crate::db::EntryData::SshKey { .. } => unreachable!(),
+            crate::db::EntryData::SshKey {
+                private_key,
+                public_key,
+                fingerprint,
+            } => {
+                req.ssh_key = Some(CiphersReqSshKey {
+                    private_key: private_key.clone(),
+                    public_key: public_key.clone(),
+                    fingerprint: fingerprint.clone(),
+                });
+            }
         }
         let client = reqwest::blocking::Client::new();
         let res = client
@@ -1328,6 +1364,7 @@ impl Client {
         notes: Option<&str>,
         folder_uuid: Option<&str>,
         history: &[crate::db::HistoryEntry],
+        revision_date: Option<&str>,
     ) -> Result<()> {
         let mut req = CiphersPutReq {
             ty: match data {
@@ -1361,6 +1398,8 @@ impl Client {
                     password: entry.password.clone(),
                 })
                 .collect(),
+            last_known_revision_date: revision_date
+                .map(std::string::ToString::to_string),
         };
         match data {
             crate::db::EntryData::Login {
@@ -1461,6 +1500,18 @@ impl Client {
             reqwest::StatusCode::UNAUTHORIZED => {
                 Err(Error::RequestUnauthorized)
             }
+            // both the official server and vaultwarden report a stale
+            // lastKnownRevisionDate as a generic validation error, so the
+            // message is the only way to tell it apart
+            reqwest::StatusCode::BAD_REQUEST => {
+                let status = res.status().as_u16();
+                let body = res.text().unwrap_or_default();
+                if body.contains("out of date") {
+                    Err(Error::CipherOutOfDate)
+                } else {
+                    Err(Error::RequestFailed { status })
+                }
+            }
             _ => Err(Error::RequestFailed {
                 status: res.status().as_u16(),
             }),
@@ -1738,24 +1789,20 @@ fn classify_login_error(error_res: &ConnectErrorRes, code: u16) -> Error {
         "invalid_client" => {
             return Error::IncorrectApiKey;
         }
-        "" => {
-            // bitwarden_rs returns an empty error and error_description for
-            // this case, for some reason
-            if error_desc.is_none() || error_desc == Some("") {
-                if let Some(error_model) = error_res.error_model.as_ref() {
-                    let message = error_model.message.as_str().to_string();
-                    match message.as_str() {
-                        "Username or password is incorrect. Try again"
-                        | "TOTP code is not a number" => {
+        // bitwarden_rs returns an empty error and error_description for
+        // this case, for some reason
+        "" if error_desc.is_none() || error_desc == Some("") => {
+            if let Some(error_model) = error_res.error_model.as_ref() {
+                let message = error_model.message.as_str().to_string();
+                match message.as_str() {
+                    "Username or password is incorrect. Try again"
+                    | "TOTP code is not a number" => {
+                        return Error::IncorrectPassword { message };
+                    }
+                    s => {
+                        if s.starts_with("Invalid TOTP code! Server time: ") {
                             return Error::IncorrectPassword { message };
                         }
-                        s => {
-                            if s.starts_with(
-                                "Invalid TOTP code! Server time: ",
-                            ) {
-                                return Error::IncorrectPassword { message };
-                            }
-                        }
                     }
                 }
             }
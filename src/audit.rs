@@ -0,0 +1,201 @@
+use crate::prelude::*;
+
+use tokio::io::AsyncWriteExt as _;
+
+// once the log grows past this size, it is rotated to audit.log.1 (and the
+// existing audit.log.1 to audit.log.2, etc)
+const MAX_SIZE: u64 = 1024 * 1024;
+const ROTATIONS: usize = 3;
+
+// the agent can handle several requests concurrently, so make sure that
+// rotation and appending don't race with each other
+static LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+// a single audit log record. this must never contain any decrypted data -
+// entries are only referred to by id.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Record {
+    pub timestamp: String,
+    pub pid: Option<i32>,
+    pub exe: Option<std::path::PathBuf>,
+    pub action: String,
+    pub entry_id: Option<String>,
+    pub outcome: Outcome,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Success,
+    Failure,
+}
+
+impl std::fmt::Display for Outcome {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Success => write!(f, "success"),
+            Self::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+impl Record {
+    pub fn new(
+        pid: Option<i32>,
+        exe: Option<std::path::PathBuf>,
+        action: &str,
+        entry_id: Option<String>,
+        outcome: Outcome,
+    ) -> Self {
+        Self {
+            timestamp: humantime::format_rfc3339_seconds(
+                std::time::SystemTime::now(),
+            )
+            .to_string(),
+            pid,
+            exe,
+            action: action.to_string(),
+            entry_id,
+            outcome,
+        }
+    }
+}
+
+pub async fn append(record: &Record) -> Result<()> {
+    let file = crate::dirs::audit_log_file();
+    let mut line = serde_json::to_string(record).map_err(|source| {
+        Error::SaveAuditLogJson {
+            source,
+            file: file.clone(),
+        }
+    })?;
+    line.push('\n');
+
+    let _lock = LOCK.lock().await;
+
+    if tokio::fs::metadata(&file)
+        .await
+        .is_ok_and(|metadata| metadata.len() >= MAX_SIZE)
+    {
+        rotate(&file).await.map_err(|source| Error::SaveAuditLog {
+            source,
+            file: file.clone(),
+        })?;
+    }
+
+    let mut fh = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .mode(0o600)
+        .open(&file)
+        .await
+        .map_err(|source| Error::SaveAuditLog {
+            source,
+            file: file.clone(),
+        })?;
+    fh.write_all(line.as_bytes())
+        .await
+        .map_err(|source| Error::SaveAuditLog { source, file })?;
+
+    Ok(())
+}
+
+// returns all records in the log (including rotated logs), oldest first
+pub fn load() -> Result<Vec<Record>> {
+    let file = crate::dirs::audit_log_file();
+    let mut records = vec![];
+    for path in (1..=ROTATIONS)
+        .rev()
+        .map(|i| rotated_file(&file, i))
+        .chain(std::iter::once(file.clone()))
+    {
+        let fh = match std::fs::File::open(&path) {
+            Ok(fh) => fh,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(source) => {
+                return Err(Error::LoadAuditLog { source, file: path })
+            }
+        };
+        records.extend(parse(std::io::BufReader::new(fh), &path)?);
+    }
+    Ok(records)
+}
+
+// a partially written or otherwise corrupted line shouldn't make the rest of
+// the log unreadable, so those lines are skipped
+fn parse(
+    r: impl std::io::BufRead,
+    path: &std::path::Path,
+) -> Result<Vec<Record>> {
+    let mut records = vec![];
+    for (i, line) in r.lines().enumerate() {
+        let line = line.map_err(|source| Error::LoadAuditLog {
+            source,
+            file: path.to_path_buf(),
+        })?;
+        if line.is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => log::warn!(
+                "skipping invalid audit log record at {}:{}: {e}",
+                path.display(),
+                i + 1
+            ),
+        }
+    }
+    Ok(records)
+}
+
+async fn rotate(file: &std::path::Path) -> std::io::Result<()> {
+    for i in (1..ROTATIONS).rev() {
+        match tokio::fs::rename(
+            rotated_file(file, i),
+            rotated_file(file, i + 1),
+        )
+        .await
+        {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    tokio::fs::rename(file, rotated_file(file, 1)).await
+}
+
+fn rotated_file(file: &std::path::Path, i: usize) -> std::path::PathBuf {
+    let mut name = file.as_os_str().to_os_string();
+    name.push(format!(".{i}"));
+    name.into()
+}
+
+#[test]
+fn test_record_format() {
+    let record = Record {
+        timestamp: "2025-01-01T00:00:00Z".to_string(),
+        pid: Some(1234),
+        exe: Some("/usr/bin/bash".into()),
+        action: "decrypt".to_string(),
+        entry_id: None,
+        outcome: Outcome::Failure,
+    };
+    let line = serde_json::to_string(&record).unwrap();
+    assert_eq!(
+        line,
+        r#"{"timestamp":"2025-01-01T00:00:00Z","pid":1234,"exe":"/usr/bin/bash","action":"decrypt","entry_id":null,"outcome":"failure"}"#
+    );
+    let parsed: Record = serde_json::from_str(&line).unwrap();
+    assert_eq!(parsed.outcome, Outcome::Failure);
+    assert_eq!(
+        rotated_file(std::path::Path::new("/tmp/audit.log"), 2),
+        std::path::Path::new("/tmp/audit.log.2")
+    );
+
+    let log = format!("{line}\n{{\"timestamp\":\n\n{line}\n");
+    let records =
+        parse(log.as_bytes(), std::path::Path::new("audit.log")).unwrap();
+    assert_eq!(records.len(), 2);
+}
@@ -28,26 +28,28 @@ pub async fn register(
             } else {
                 None
             };
-            let client_id = rbw::pinentry::getpin(
-                &config_pinentry().await?,
-                "API key client__id",
-                &format!("Log in to {host}"),
-                err.as_deref(),
-                environment,
-                false,
-            )
-            .await
-            .context("failed to read client_id from pinentry")?;
-            let client_secret = rbw::pinentry::getpin(
-                &config_pinentry().await?,
-                "API key client__secret",
-                &format!("Log in to {host}"),
-                err.as_deref(),
-                environment,
-                false,
-            )
-            .await
-            .context("failed to read client_secret from pinentry")?;
+            let client_id = config_prompt()
+                .await?
+                .getpin(
+                    "API key client__id",
+                    &format!("Log in to {host}"),
+                    err.as_deref(),
+                    environment,
+                    false,
+                )
+                .await
+                .context("failed to read client_id")?;
+            let client_secret = config_prompt()
+                .await?
+                .getpin(
+                    "API key client__secret",
+                    &format!("Log in to {host}"),
+                    err.as_deref(),
+                    environment,
+                    false,
+                )
+                .await
+                .context("failed to read client_secret")?;
             let apikey = rbw::locked::ApiKey::new(client_id, client_secret);
             match rbw::actions::register(&email, apikey.clone()).await {
                 Ok(()) => {
@@ -103,16 +105,17 @@ pub async fn login(
             } else {
                 None
             };
-            let password = rbw::pinentry::getpin(
-                &config_pinentry().await?,
-                "Master Password",
-                &format!("Log in to {host}"),
-                err.as_deref(),
-                environment,
-                true,
-            )
-            .await
-            .context("failed to read password from pinentry")?;
+            let password = config_prompt()
+                .await?
+                .getpin(
+                    "Master Password",
+                    &format!("Log in to {host}"),
+                    err.as_deref(),
+                    environment,
+                    true,
+                )
+                .await
+                .context("failed to read password")?;
             match rbw::actions::login(&email, password.clone(), None, None)
                 .await
             {
@@ -135,8 +138,8 @@ pub async fn login(
                         parallelism,
                         protected_key,
                         password,
-                        db,
                         email,
+                        environment,
                     )
                     .await?;
                     break 'attempts;
@@ -191,8 +194,8 @@ pub async fn login(
                                 parallelism,
                                 protected_key,
                                 password,
-                                db,
                                 email,
+                                environment,
                             )
                             .await?;
                             break 'attempts;
@@ -247,16 +250,17 @@ async fn two_factor(
         } else {
             None
         };
-        let code = rbw::pinentry::getpin(
-            &config_pinentry().await?,
-            provider.header(),
-            provider.message(),
-            err.as_deref(),
-            environment,
-            provider.grab(),
-        )
-        .await
-        .context("failed to read code from pinentry")?;
+        let code = config_prompt()
+            .await?
+            .getpin(
+                provider.header(),
+                provider.message(),
+                err.as_deref(),
+                environment,
+                provider.grab(),
+            )
+            .await
+            .context("failed to read code")?;
         let code = std::str::from_utf8(code.password())
             .context("code was not valid utf8")?;
         match rbw::actions::login(
@@ -326,9 +330,11 @@ async fn login_success(
     parallelism: Option<u32>,
     protected_key: String,
     password: rbw::locked::Password,
-    mut db: rbw::db::Db,
     email: String,
+    environment: &rbw::protocol::Environment,
 ) -> anyhow::Result<()> {
+    let lock = lock_db().await?;
+    let mut db = load_db().await.unwrap_or_else(|_| rbw::db::Db::new());
     db.access_token = Some(access_token.clone());
     db.refresh_token = Some(refresh_token.clone());
     db.kdf = Some(kdf);
@@ -336,7 +342,8 @@ async fn login_success(
     db.memory = memory;
     db.parallelism = parallelism;
     db.protected_key = Some(protected_key.clone());
-    save_db(&db).await?;
+    save_db(&db, &lock).await?;
+    drop(lock);
 
     sync(None, state.clone()).await?;
     let db = load_db().await?;
@@ -361,9 +368,7 @@ async fn login_success(
 
     match res {
         Ok((keys, org_keys)) => {
-            let mut state = state.lock().await;
-            state.priv_key = Some(keys);
-            state.org_keys = Some(org_keys);
+            unlock_success(state, keys, org_keys, environment).await?;
         }
         Err(e) => return Err(e).context("failed to unlock database"),
     }
@@ -378,32 +383,44 @@ async fn unlock_state(
     if state.lock().await.needs_unlock() {
         let db = load_db().await?;
 
-        let Some(kdf) = db.kdf else {
-            return Err(anyhow::anyhow!("failed to find kdf type in db"));
-        };
+        let (kdf, iterations, memory, parallelism) = db_kdf(&db)?;
 
-        let Some(iterations) = db.iterations else {
-            return Err(anyhow::anyhow!(
-                "failed to find number of iterations in db"
-            ));
-        };
-
-        let memory = db.memory;
-        let parallelism = db.parallelism;
-
-        let Some(protected_key) = db.protected_key else {
+        let Some(protected_key) = &db.protected_key else {
             return Err(anyhow::anyhow!(
                 "failed to find protected key in db"
             ));
         };
-        let Some(protected_private_key) = db.protected_private_key else {
+        let Some(protected_private_key) = &db.protected_private_key else {
             return Err(anyhow::anyhow!(
                 "failed to find protected private key in db"
             ));
         };
 
+        if state.lock().await.session_keyring {
+            if let Some((keys, org_keys, lifetime_deadline)) =
+                unlock_keyring(&db, protected_private_key)
+            {
+                unlock_success(state.clone(), keys, org_keys, environment)
+                    .await?;
+                if let Some(deadline) = lifetime_deadline {
+                    let mut state = state.lock().await;
+                    state.resume_lifetime(deadline);
+                    state.store_keys();
+                }
+                return Ok(());
+            }
+        }
+
         let email = config_email().await?;
 
+        if let Some((keys, org_keys)) =
+            unlock_pin(&db, &email, protected_private_key, environment)
+                .await?
+        {
+            unlock_success(state, keys, org_keys, environment).await?;
+            return Ok(());
+        }
+
         let mut err_msg = None;
         for i in 1_u8..=3 {
             let err = if i > 1 {
@@ -413,19 +430,20 @@ async fn unlock_state(
             } else {
                 None
             };
-            let password = rbw::pinentry::getpin(
-                &config_pinentry().await?,
-                "Master Password",
-                &format!(
-                    "Unlock the local database for '{}'",
-                    rbw::dirs::profile()
-                ),
-                err.as_deref(),
-                environment,
-                true,
-            )
-            .await
-            .context("failed to read password from pinentry")?;
+            let password = config_prompt()
+                .await?
+                .getpin(
+                    "Master Password",
+                    &format!(
+                        "Unlock the local database for '{}'",
+                        rbw::dirs::profile()
+                    ),
+                    err.as_deref(),
+                    environment,
+                    true,
+                )
+                .await
+                .context("failed to read password")?;
             match rbw::actions::unlock(
                 &email,
                 &password,
@@ -433,12 +451,13 @@ async fn unlock_state(
                 iterations,
                 memory,
                 parallelism,
-                &protected_key,
-                &protected_private_key,
+                protected_key,
+                protected_private_key,
                 &db.protected_org_keys,
             ) {
                 Ok((keys, org_keys)) => {
-                    unlock_success(state, keys, org_keys).await?;
+                    unlock_success(state, keys, org_keys, environment)
+                        .await?;
                     break;
                 }
                 Err(rbw::error::Error::IncorrectPassword { message }) => {
@@ -458,6 +477,197 @@ async fn unlock_state(
     Ok(())
 }
 
+// tries to unlock using keys left in the session keyring by a previous
+// agent. returns None if there are no usable keys there.
+fn unlock_keyring(
+    db: &rbw::db::Db,
+    protected_private_key: &str,
+) -> Option<(
+    rbw::locked::Keys,
+    std::collections::HashMap<String, rbw::locked::Keys>,
+    Option<std::time::SystemTime>,
+)> {
+    let stored = match rbw::keyring::load() {
+        Ok(stored) => stored?,
+        Err(e) => {
+            log::warn!("failed to load keys from session keyring: {e:#}");
+            return None;
+        }
+    };
+    if stored
+        .lifetime_deadline
+        .is_some_and(|deadline| deadline <= std::time::SystemTime::now())
+    {
+        if let Err(e) = rbw::keyring::remove() {
+            log::warn!("failed to remove keys from session keyring: {e:#}");
+        }
+        return None;
+    }
+    match rbw::actions::unlock_with_key(
+        stored.keys,
+        protected_private_key,
+        &db.protected_org_keys,
+    ) {
+        Ok((keys, org_keys)) => {
+            Some((keys, org_keys, stored.lifetime_deadline))
+        }
+        // most likely the keys are for a different account
+        Err(e) => {
+            log::warn!(
+                "failed to unlock with keys from session keyring: {e}"
+            );
+            if let Err(e) = rbw::keyring::remove() {
+                log::warn!(
+                    "failed to remove keys from session keyring: {e:#}"
+                );
+            }
+            None
+        }
+    }
+}
+
+// tries to unlock using the pin, if one has been set. returns None if the
+// master password should be used instead, either because there is no pin,
+// the user cancelled the prompt, or there were too many incorrect attempts.
+async fn unlock_pin(
+    db: &rbw::db::Db,
+    email: &str,
+    protected_private_key: &str,
+    environment: &rbw::protocol::Environment,
+) -> anyhow::Result<
+    Option<(
+        rbw::locked::Keys,
+        std::collections::HashMap<String, rbw::locked::Keys>,
+    )>,
+> {
+    let server = rbw::config::Config::load_async().await?.server_name();
+    let Some(mut pin) = rbw::pin::Pin::load_async(&server, email).await?
+    else {
+        return Ok(None);
+    };
+    let (kdf, iterations, memory, parallelism) = db_kdf(db)?;
+
+    let mut err_msg = None;
+    while pin.attempts_remaining() > 0 {
+        let err = err_msg.as_ref().map(|msg| {
+            format!("{msg} ({} attempts remaining)", pin.attempts_remaining())
+        });
+        let password = match config_prompt()
+            .await?
+            .getpin(
+                "PIN",
+                &format!(
+                    "Unlock the local database for '{}' (cancel to use the \
+                    master password instead)",
+                    rbw::dirs::profile()
+                ),
+                err.as_deref(),
+                environment,
+                true,
+            )
+            .await
+        {
+            Ok(password) => password,
+            Err(rbw::error::Error::PinentryCancelled) => return Ok(None),
+            Err(e) => return Err(e).context("failed to read pin"),
+        };
+        match rbw::actions::unlock_with_pin(
+            email,
+            &pin,
+            &password,
+            kdf,
+            iterations,
+            memory,
+            parallelism,
+            protected_private_key,
+            &db.protected_org_keys,
+        ) {
+            Ok(keys) => {
+                if pin.failed_attempts > 0 {
+                    pin.failed_attempts = 0;
+                    pin.save_async(&server, email).await?;
+                }
+                return Ok(Some(keys));
+            }
+            Err(rbw::error::Error::IncorrectPassword { message }) => {
+                pin.failed_attempts += 1;
+                pin.save_async(&server, email).await?;
+                err_msg = Some(message);
+            }
+            // most likely the account's keys have changed since the pin
+            // was set
+            Err(e) => {
+                log::warn!("failed to unlock with pin, removing it: {e}");
+                rbw::pin::Pin::remove(&server, email)?;
+                return Ok(None);
+            }
+        }
+    }
+
+    log::warn!("too many incorrect pin attempts, removing the pin");
+    rbw::pin::Pin::remove(&server, email)?;
+    Ok(None)
+}
+
+pub async fn set_pin(
+    sock: &mut crate::sock::Sock,
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    environment: &rbw::protocol::Environment,
+) -> anyhow::Result<()> {
+    unlock_state(state.clone(), environment).await?;
+
+    let db = load_db().await?;
+    let (kdf, iterations, memory, parallelism) = db_kdf(&db)?;
+    let config = rbw::config::Config::load_async().await?;
+    let email = config_email().await?;
+
+    let prompt = rbw::prompt::Backend::new(&config);
+    let desc = format!(
+        "Set a PIN to unlock the local database for '{}'",
+        rbw::dirs::profile()
+    );
+    let mut err = None;
+    let password = loop {
+        let password = prompt
+            .getpin("PIN", &desc, err, environment, true)
+            .await
+            .context("failed to read pin")?;
+        if password.password().is_empty() {
+            err = Some("PIN must not be empty");
+            continue;
+        }
+        let confirm = prompt
+            .getpin("Confirm PIN", &desc, None, environment, true)
+            .await
+            .context("failed to read pin")?;
+        if password.password() == confirm.password() {
+            break password;
+        }
+        err = Some("PINs do not match");
+    };
+
+    let Some(keys) = state.lock().await.priv_key.clone() else {
+        return Err(anyhow::anyhow!(
+            "failed to find decryption keys in in-memory state"
+        ));
+    };
+    let pin = rbw::pin::Pin::new(
+        &email,
+        &password,
+        kdf,
+        iterations,
+        memory,
+        parallelism,
+        &keys,
+    )
+    .context("failed to encrypt keys with pin")?;
+    pin.save_async(&config.server_name(), &email).await?;
+
+    respond_ack(sock).await?;
+
+    Ok(())
+}
+
 pub async fn unlock(
     sock: &mut crate::sock::Sock,
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
@@ -474,10 +684,88 @@ async fn unlock_success(
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
     keys: rbw::locked::Keys,
     org_keys: std::collections::HashMap<String, rbw::locked::Keys>,
+    environment: &rbw::protocol::Environment,
 ) -> anyhow::Result<()> {
     let mut state = state.lock().await;
     state.priv_key = Some(keys);
     state.org_keys = Some(org_keys);
+    state.unlock_environment = Some(environment.clone());
+    let modified = db_modified().await;
+    if let Ok(mut db) = load_db().await {
+        state.set_ssh_key_index(&db.entries);
+        db.apply_pending();
+        state.set_search_index(&db.entries, modified);
+    }
+    state.set_lifetime();
+    state.store_keys();
+    Ok(())
+}
+
+pub async fn authorize_client(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    environment: &rbw::protocol::Environment,
+    peer: &crate::peer::Peer,
+) -> anyhow::Result<()> {
+    let client_policy = state.lock().await.client_policy;
+    if client_policy == rbw::config::ClientPolicy::Allow {
+        return Ok(());
+    }
+
+    let Some(exe) = &peer.exe else {
+        return Err(anyhow::anyhow!(
+            "couldn't determine the executable for {peer}, refusing access"
+        ));
+    };
+    {
+        let state = state.lock().await;
+        if state.allowed_clients.contains(exe)
+            || state.approved_clients.contains(exe)
+        {
+            return Ok(());
+        }
+    }
+
+    if client_policy == rbw::config::ClientPolicy::Allowlist {
+        return Err(anyhow::anyhow!(
+            "{peer} is not in allowed_clients, refusing access"
+        ));
+    }
+
+    // make sure we have a trusted environment to show the prompt in
+    unlock_state(state.clone(), environment).await?;
+    let Some(unlock_environment) =
+        state.lock().await.unlock_environment.clone()
+    else {
+        return Err(anyhow::anyhow!(
+            "failed to find unlock environment in in-memory state"
+        ));
+    };
+
+    // the executable path is chosen by the client, and file names can
+    // contain newlines. escaping it here (in addition to the escaping that
+    // the pinentry backend does) means that those show up as %0A with every
+    // prompt backend, rather than as extra lines of text in the prompt.
+    let confirmed = config_prompt()
+        .await?
+        .confirm(
+            &format!(
+                "Allow {} to access the '{}' vault?",
+                rbw::pinentry::escape(&peer.to_string()),
+                rbw::dirs::profile()
+            ),
+            "Allow",
+            "Deny",
+            &unlock_environment,
+        )
+        .await
+        .context("failed to confirm client access")?;
+    if !confirmed {
+        return Err(anyhow::anyhow!("access denied for {peer}"));
+    }
+
+    log::info!("allowing access for {peer} until the agent is locked");
+    state.lock().await.approved_clients.insert(exe.clone());
+
     Ok(())
 }
 
@@ -509,8 +797,12 @@ pub async fn sync(
     sock: Option<&mut crate::sock::Sock>,
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
 ) -> anyhow::Result<()> {
-    let mut db = load_db().await?;
+    // the db lock is only held while writing to the db, so this keeps
+    // syncs which overlap from saving their results out of order
+    let sync_lock = state.lock().await.sync_lock.clone();
+    let _sync_lock = sync_lock.lock().await;
 
+    let db = load_db().await?;
     let access_token = if let Some(access_token) = &db.access_token {
         access_token.clone()
     } else {
@@ -521,21 +813,73 @@ pub async fn sync(
     } else {
         return Err(anyhow::anyhow!("failed to find refresh token in db"));
     };
+    // the full sync can take a while (or hang, if the server does), so the
+    // db isn't locked until we have something to write
     let (
-        access_token,
-        (protected_key, protected_private_key, protected_org_keys, entries),
+        mut new_access_token,
+        (
+            mut protected_key,
+            mut protected_private_key,
+            mut protected_org_keys,
+            mut entries,
+        ),
     ) = rbw::actions::sync(&access_token, &refresh_token)
         .await
         .context("failed to sync database from server")?;
-    state.lock().await.set_master_password_reprompt(&entries);
-    if let Some(access_token) = access_token {
+
+    // the db is loaded again after locking, since the cli may have changed
+    // it in the meantime
+    let mut lock = lock_db().await?;
+    let mut db = load_db().await?;
+
+    // changes made while offline are sent now that we know the server is
+    // reachable, and then the results are synced back down. the lock is
+    // held while sending, since otherwise the cli could modify or discard
+    // queued changes which we have already sent.
+    if !db.pending.is_empty() {
+        if let Some(access_token) = new_access_token.take() {
+            db.access_token = Some(access_token);
+        }
+        let sent = send_pending(&mut db, &entries).await?;
+        save_db(&db, &lock).await?;
+        if sent {
+            drop(lock);
+            // unwrap is safe because send_pending doesn't clear it
+            let access_token = db.access_token.clone().unwrap();
+            (
+                new_access_token,
+                (
+                    protected_key,
+                    protected_private_key,
+                    protected_org_keys,
+                    entries,
+                ),
+            ) = rbw::actions::sync(&access_token, &refresh_token)
+                .await
+                .context("failed to sync database from server")?;
+            lock = lock_db().await?;
+            db = load_db().await?;
+        }
+    }
+
+    {
+        let mut state = state.lock().await;
+        state.set_master_password_reprompt(&entries);
+        state.set_entry_ids(&entries);
+        state.set_ssh_key_index(&entries);
+    }
+    if let Some(access_token) = new_access_token {
         db.access_token = Some(access_token);
     }
     db.protected_key = Some(protected_key);
     db.protected_private_key = Some(protected_private_key);
     db.protected_org_keys = protected_org_keys;
     db.entries = entries;
-    save_db(&db).await?;
+    save_db(&db, &lock).await?;
+    drop(lock);
+    if let Err(e) = load_search_index(state.clone()).await {
+        log::warn!("failed to rebuild search index: {e:#}");
+    }
 
     if let Err(e) = subscribe_to_notifications(state.clone()).await {
         eprintln!("failed to subscribe to notifications: {e}");
@@ -548,6 +892,111 @@ pub async fn sync(
     Ok(())
 }
 
+// sends the queued changes to the server, given the entries that are
+// currently on the server. returns whether anything was changed on the
+// server.
+async fn send_pending(
+    db: &mut rbw::db::Db,
+    entries: &[rbw::db::Entry],
+) -> anyhow::Result<bool> {
+    let mut sent = false;
+    let pending = std::mem::take(&mut db.pending);
+    let mut pending = pending.into_iter();
+    while let Some(mut change) = pending.next() {
+        let server =
+            entries.iter().find(|entry| entry.id == change.action.id());
+        match (&change.action, server) {
+            // already removed on the server, so there's nothing left to do
+            (rbw::db::PendingAction::Remove { .. }, None) => continue,
+            (
+                rbw::db::PendingAction::Edit { orig, .. }
+                | rbw::db::PendingAction::Remove { orig },
+                server,
+            ) if server.is_none_or(|server| modified_since(server, orig)) => {
+                change.conflict = true;
+                db.pending.push(change);
+                continue;
+            }
+            _ => {}
+        }
+
+        // unwrap is safe because the caller has already checked these
+        let access_token = db.access_token.clone().unwrap();
+        let refresh_token = db.refresh_token.clone().unwrap();
+        let action = change.action.clone();
+        // the api functions for modifying entries are blocking
+        let res = tokio::task::spawn_blocking(move || match &action {
+            rbw::db::PendingAction::Add { entry } => rbw::actions::add(
+                &access_token,
+                &refresh_token,
+                &entry.name,
+                &entry.data,
+                entry.notes.as_deref(),
+                entry.folder_id.as_deref(),
+            ),
+            rbw::db::PendingAction::Edit { entry, .. } => rbw::actions::edit(
+                &access_token,
+                &refresh_token,
+                &entry.id,
+                entry.org_id.as_deref(),
+                &entry.name,
+                &entry.data,
+                &entry.fields,
+                entry.notes.as_deref(),
+                entry.folder_id.as_deref(),
+                &entry.history,
+                entry.revision_date.as_deref(),
+            ),
+            rbw::db::PendingAction::Remove { orig } => {
+                rbw::actions::remove(&access_token, &refresh_token, &orig.id)
+            }
+        })
+        .await?;
+        match res {
+            Ok((access_token, ())) => {
+                if let Some(access_token) = access_token {
+                    db.access_token = Some(access_token);
+                }
+                sent = true;
+            }
+            Err(rbw::error::Error::CipherOutOfDate) => {
+                change.conflict = true;
+                db.pending.push(change);
+            }
+            Err(e) => {
+                eprintln!(
+                    "failed to send queued {} to the server: {e}",
+                    change.action.name()
+                );
+                let offline = e.is_offline();
+                db.pending.push(change);
+                if offline {
+                    db.pending.extend(pending);
+                    break;
+                }
+            }
+        }
+    }
+    Ok(sent)
+}
+
+fn modified_since(server: &rbw::db::Entry, orig: &rbw::db::Entry) -> bool {
+    match (&server.revision_date, &orig.revision_date) {
+        (Some(server), Some(orig)) => server != orig,
+        // entries queued before revision dates were stored. the server
+        // always sends one, so it can't be part of the comparison.
+        _ => {
+            rbw::db::Entry {
+                revision_date: None,
+                ..server.clone()
+            } != rbw::db::Entry {
+                revision_date: None,
+                ..orig.clone()
+            }
+        }
+    }
+}
+
 async fn decrypt_cipher(
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
     environment: &rbw::protocol::Environment,
@@ -559,24 +1008,8 @@ async fn decrypt_cipher(
     if !state.master_password_reprompt_initialized() {
         let db = load_db().await?;
         state.set_master_password_reprompt(&db.entries);
+        state.set_entry_ids(&db.entries);
     }
-    let Some(keys) = state.key(org_id) else {
-        return Err(anyhow::anyhow!(
-            "failed to find decryption keys in in-memory state"
-        ));
-    };
-    let entry_key = if let Some(entry_key) = entry_key {
-        let key_cipherstring =
-            rbw::cipherstring::CipherString::new(entry_key)
-                .context("failed to parse individual item encryption key")?;
-        Some(rbw::locked::Keys::new(
-            key_cipherstring.decrypt_locked_symmetric(keys).context(
-                "failed to decrypt individual item encryption key",
-            )?,
-        ))
-    } else {
-        None
-    };
 
     let mut sha256 = sha2::Sha256::new();
     sha256.update(cipherstring);
@@ -622,16 +1055,17 @@ async fn decrypt_cipher(
             } else {
                 None
             };
-            let password = rbw::pinentry::getpin(
-                &config_pinentry().await?,
-                "Master Password",
-                "Accessing this entry requires the master password",
-                err.as_deref(),
-                environment,
-                true,
-            )
-            .await
-            .context("failed to read password from pinentry")?;
+            let password = config_prompt()
+                .await?
+                .getpin(
+                    "Master Password",
+                    "Accessing this entry requires the master password",
+                    err.as_deref(),
+                    environment,
+                    true,
+                )
+                .await
+                .context("failed to read password")?;
             match rbw::actions::unlock(
                 &email,
                 &password,
@@ -660,16 +1094,7 @@ async fn decrypt_cipher(
         }
     }
 
-    let cipherstring = rbw::cipherstring::CipherString::new(cipherstring)
-        .context("failed to parse encrypted secret")?;
-    let plaintext = String::from_utf8(
-        cipherstring
-            .decrypt_symmetric(keys, entry_key.as_ref())
-            .context("failed to decrypt encrypted secret")?,
-    )
-    .context("failed to parse decrypted secret")?;
-
-    Ok(plaintext)
+    state.decrypt(cipherstring, entry_key, org_id)
 }
 
 pub async fn decrypt(
@@ -711,6 +1136,27 @@ pub async fn encrypt(
     Ok(())
 }
 
+pub async fn index(
+    sock: &mut crate::sock::Sock,
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+) -> anyhow::Result<()> {
+    if state.lock().await.needs_unlock() {
+        return Err(anyhow::anyhow!("agent is locked"));
+    }
+    ensure_search_index(state.clone()).await?;
+    let entries = state
+        .lock()
+        .await
+        .search_index
+        .as_ref()
+        .map(|index| index.entries().to_vec())
+        .ok_or_else(|| anyhow::anyhow!("agent is locked"))?;
+
+    respond_index(sock, entries).await?;
+
+    Ok(())
+}
+
 #[cfg(feature = "clipboard")]
 pub async fn clipboard_store(
     sock: &mut crate::sock::Sock,
@@ -752,6 +1198,33 @@ pub async fn version(sock: &mut crate::sock::Sock) -> anyhow::Result<()> {
     Ok(())
 }
 
+pub async fn audit<T: Sync>(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    peer: &crate::peer::Peer,
+    action: &str,
+    entry_id: Option<String>,
+    res: &anyhow::Result<T>,
+) {
+    if !state.lock().await.audit_log {
+        return;
+    }
+
+    let record = rbw::audit::Record::new(
+        peer.pid,
+        peer.exe.clone(),
+        action,
+        entry_id,
+        if res.is_ok() {
+            rbw::audit::Outcome::Success
+        } else {
+            rbw::audit::Outcome::Failure
+        },
+    );
+    if let Err(e) = rbw::audit::append(&record).await {
+        log::warn!("failed to write to audit log: {e:#}");
+    }
+}
+
 async fn respond_ack(sock: &mut crate::sock::Sock) -> anyhow::Result<()> {
     sock.send(&rbw::protocol::Response::Ack).await?;
 
@@ -768,6 +1241,16 @@ async fn respond_decrypt(
     Ok(())
 }
 
+async fn respond_index(
+    sock: &mut crate::sock::Sock,
+    entries: Vec<rbw::protocol::IndexEntry>,
+) -> anyhow::Result<()> {
+    sock.send(&rbw::protocol::Response::Index { entries })
+        .await?;
+
+    Ok(())
+}
+
 async fn respond_encrypt(
     sock: &mut crate::sock::Sock,
     cipherstring: String,
@@ -786,6 +1269,22 @@ async fn config_email() -> anyhow::Result<String> {
     )
 }
 
+fn db_kdf(
+    db: &rbw::db::Db,
+) -> anyhow::Result<(rbw::api::KdfType, u32, Option<u32>, Option<u32>)> {
+    let Some(kdf) = db.kdf else {
+        return Err(anyhow::anyhow!("failed to find kdf type in db"));
+    };
+
+    let Some(iterations) = db.iterations else {
+        return Err(anyhow::anyhow!(
+            "failed to find number of iterations in db"
+        ));
+    };
+
+    Ok((kdf, iterations, db.memory, db.parallelism))
+}
+
 async fn load_db() -> anyhow::Result<rbw::db::Db> {
     let config = rbw::config::Config::load_async().await?;
     if let Some(email) = &config.email {
@@ -797,10 +1296,33 @@ async fn load_db() -> anyhow::Result<rbw::db::Db> {
     }
 }
 
-async fn save_db(db: &rbw::db::Db) -> anyhow::Result<()> {
+// the modification time of the db file, used to tell whether the search
+// index is out of date
+async fn db_modified() -> Option<std::time::SystemTime> {
+    let config = rbw::config::Config::load_async().await.ok()?;
+    let email = config.email.as_ref()?;
+    let file = rbw::dirs::db_file(&config.server_name(), email);
+    tokio::fs::metadata(file).await.ok()?.modified().ok()
+}
+
+async fn lock_db() -> anyhow::Result<rbw::db::Lock> {
     let config = rbw::config::Config::load_async().await?;
     if let Some(email) = &config.email {
-        db.save_async(&config.server_name(), email)
+        rbw::db::Db::lock_async(&config.server_name(), email)
+            .await
+            .map_err(anyhow::Error::new)
+    } else {
+        Err(anyhow::anyhow!("failed to find email address in config"))
+    }
+}
+
+async fn save_db(
+    db: &rbw::db::Db,
+    lock: &rbw::db::Lock,
+) -> anyhow::Result<()> {
+    let config = rbw::config::Config::load_async().await?;
+    if let Some(email) = &config.email {
+        db.save_async(&config.server_name(), email, config.db_backups, lock)
             .await
             .map_err(anyhow::Error::new)
     } else {
@@ -813,9 +1335,9 @@ async fn config_base_url() -> anyhow::Result<String> {
     Ok(config.base_url())
 }
 
-async fn config_pinentry() -> anyhow::Result<String> {
+async fn config_prompt() -> anyhow::Result<rbw::prompt::Backend> {
     let config = rbw::config::Config::load_async().await?;
-    Ok(config.pinentry)
+    Ok(rbw::prompt::Backend::new(&config))
 }
 
 pub async fn subscribe_to_notifications(
@@ -850,103 +1372,282 @@ pub async fn subscribe_to_notifications(
         .map_or_else(|| Ok(()), |err| Err(anyhow::anyhow!(err.to_string())))
 }
 
-pub async fn get_ssh_public_keys(
+pub async fn get_ssh_identities(
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
-) -> anyhow::Result<Vec<String>> {
+    host: Option<&str>,
+    bindings: &crate::ssh_bind::Bindings,
+) -> anyhow::Result<Vec<crate::ssh_keys::Identity>> {
+    let config = rbw::config::Config::load_async().await?;
+    // keys added via ssh-add are offered first, since they were most likely
+    // added for a specific purpose
+    let mut identities: Vec<_> = state
+        .lock()
+        .await
+        .ssh_added_keys()
+        .iter()
+        .filter(|key| bindings.permits(&key.destinations, None))
+        .map(crate::ssh_keys::AddedKey::identity)
+        .collect();
+
+    if state.lock().await.ssh_refuse_forwarded && bindings.is_forwarded() {
+        return Ok(identities);
+    }
+
     let environment = {
         let state = state.lock().await;
-        state.set_timeout();
+        state.set_timeout_for("ssh_identities");
         state.last_environment().clone()
     };
-    unlock_state(state.clone(), &environment).await?;
-
-    let db = load_db().await?;
-    let mut pubkeys = Vec::new();
+    let res = async {
+        unlock_state(state.clone(), &environment).await?;
+        ensure_ssh_key_index(state.clone()).await
+    }
+    .await;
+    // added keys are still usable if the vault can't be unlocked
+    if let Err(e) = res {
+        if identities.is_empty() {
+            return Err(e);
+        }
+        log::warn!("failed to load ssh keys from the vault: {e:#}");
+        return Ok(identities);
+    }
 
-    for entry in db.entries {
-        if let rbw::db::EntryData::SshKey {
-            public_key: Some(encrypted),
-            ..
-        } = &entry.data
-        {
-            let plaintext = decrypt_cipher(
-                state.clone(),
-                &environment,
-                encrypted,
-                entry.key.as_deref(),
-                entry.org_id.as_deref(),
+    let state = state.lock().await;
+    let Some(index) = &state.ssh_key_index else {
+        return Err(anyhow::anyhow!(
+            "failed to find ssh key index in in-memory state"
+        ));
+    };
+    identities.extend(
+        index
+            .offered(
+                config.ssh_folder.as_deref(),
+                host,
+                config.ssh_max_identities,
+                bindings,
             )
-            .await?;
+            .into_iter()
+            .flat_map(crate::ssh_keys::Key::identities),
+    );
 
-            pubkeys.push(plaintext);
-        }
-    }
+    Ok(identities)
+}
 
-    Ok(pubkeys)
+pub struct SshPrivateKey {
+    pub entry_id: Option<String>,
+    pub name: String,
+    pub confirm: bool,
+    pub private_key: ssh_agent_lib::ssh_key::PrivateKey,
+    pub destinations:
+        Vec<ssh_agent_lib::proto::extension::DestinationConstraint>,
 }
 
 pub async fn find_ssh_private_key(
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
     request_public_key: ssh_agent_lib::ssh_key::PublicKey,
-) -> anyhow::Result<ssh_agent_lib::ssh_key::PrivateKey> {
+    request_certificate: Option<ssh_agent_lib::ssh_key::Certificate>,
+    host: Option<&str>,
+    bindings: &crate::ssh_bind::Bindings,
+) -> anyhow::Result<SshPrivateKey> {
+    // keys added via ssh-add aren't stored in the vault, so they can be
+    // used without unlocking
+    if let Some(key) =
+        state.lock().await.ssh_added_keys().iter().find(|key| {
+            key.matches(
+                request_public_key.key_data(),
+                request_certificate.as_ref(),
+            )
+        })
+    {
+        return Ok(SshPrivateKey {
+            entry_id: None,
+            name: key.private_key.comment().to_string(),
+            confirm: key.confirm,
+            private_key: key.private_key.clone(),
+            destinations: key.destinations.clone(),
+        });
+    }
+
+    let config = rbw::config::Config::load_async().await?;
+    if state.lock().await.ssh_refuse_forwarded && bindings.is_forwarded() {
+        return Err(anyhow::anyhow!(
+            "refusing to use keys from the vault over a forwarded agent \
+            connection"
+        ));
+    }
     let environment = {
         let state = state.lock().await;
-        state.set_timeout();
+        state.set_timeout_for("ssh_sign");
         state.last_environment().clone()
     };
     unlock_state(state.clone(), &environment).await?;
+    ensure_ssh_key_index(state.clone()).await?;
 
-    let request_bytes = request_public_key.to_bytes();
+    let (
+        entry_id,
+        name,
+        confirm,
+        destinations,
+        private_key,
+        entry_key,
+        org_id,
+    ) = {
+        let state = state.lock().await;
+        let Some(index) = &state.ssh_key_index else {
+            return Err(anyhow::anyhow!(
+                "failed to find ssh key index in in-memory state"
+            ));
+        };
+        let key = index
+            .find(&request_public_key, config.ssh_folder.as_deref(), host)
+            .filter(|key| {
+                request_certificate.as_ref().is_none_or(|certificate| {
+                    key.certificates.contains(certificate)
+                })
+            })
+            .ok_or_else(|| {
+                anyhow::anyhow!("No matching private key found")
+            })?;
+        (
+            key.entry_id.clone(),
+            key.name.clone(),
+            key.confirm,
+            key.destinations.clone(),
+            key.private_key.clone(),
+            key.entry_key.clone(),
+            key.org_id.clone(),
+        )
+    };
 
+    let private_key_enc = private_key.ok_or_else(|| {
+        anyhow::anyhow!("Matching entry has no private key")
+    })?;
+    let private_key_plaintext = decrypt_cipher(
+        state.clone(),
+        &environment,
+        &private_key_enc,
+        entry_key.as_deref(),
+        org_id.as_deref(),
+    )
+    .await?;
+    let private_key = ssh_agent_lib::ssh_key::PrivateKey::from_openssh(
+        private_key_plaintext,
+    )
+    .map_err(anyhow::Error::new)?;
+
+    Ok(SshPrivateKey {
+        entry_id: Some(entry_id),
+        name,
+        confirm,
+        private_key,
+        destinations,
+    })
+}
+
+pub async fn add_ssh_key(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    key: crate::ssh_keys::AddedKey,
+) {
+    let mut state = state.lock().await;
+    let keys = state.ssh_added_keys();
+    // adding a key which is already present replaces it (and its
+    // constraints), like ssh-agent does
+    keys.retain(|existing| {
+        !existing.matches(
+            key.private_key.public_key().key_data(),
+            key.certificate.as_ref(),
+        )
+    });
+    keys.push(key);
+}
+
+pub async fn remove_ssh_key(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    public_key: &ssh_agent_lib::ssh_key::public::KeyData,
+    certificate: Option<&ssh_agent_lib::ssh_key::Certificate>,
+) -> anyhow::Result<()> {
+    let mut state = state.lock().await;
+    let keys = state.ssh_added_keys();
+    let len = keys.len();
+    keys.retain(|key| !key.matches(public_key, certificate));
+    if keys.len() == len {
+        // keys stored in the vault can't be removed from the agent
+        return Err(anyhow::anyhow!("no matching added key found"));
+    }
+    Ok(())
+}
+
+pub async fn remove_all_ssh_keys(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+) {
+    state.lock().await.ssh_added_keys().clear();
+}
+
+// the index is normally built when unlocking or syncing, but it can be
+// missing if the agent was unlocked before the db was first synced
+async fn ensure_ssh_key_index(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+) -> anyhow::Result<()> {
+    if state.lock().await.ssh_key_index.is_some() {
+        return Ok(());
+    }
     let db = load_db().await?;
+    state.lock().await.set_ssh_key_index(&db.entries);
+    Ok(())
+}
 
-    for entry in db.entries {
-        if let rbw::db::EntryData::SshKey {
-            private_key,
-            public_key,
-            ..
-        } = &entry.data
-        {
-            let Some(public_key_enc) = public_key else {
-                continue;
-            };
-            let public_key_plaintext = decrypt_cipher(
-                state.clone(),
-                &environment,
-                public_key_enc,
-                entry.key.as_deref(),
-                entry.org_id.as_deref(),
-            )
-            .await?;
-            let public_key_bytes =
-                ssh_agent_lib::ssh_key::PublicKey::from_openssh(
-                    &public_key_plaintext,
-                )
-                .map_err(anyhow::Error::new)?
-                .to_bytes();
-
-            if public_key_bytes == request_bytes {
-                let private_key_enc =
-                    private_key.as_ref().ok_or_else(|| {
-                        anyhow::anyhow!("Matching entry has no private key")
-                    })?;
-
-                let private_key_plaintext = decrypt_cipher(
-                    state.clone(),
-                    &environment,
-                    private_key_enc,
-                    entry.key.as_deref(),
-                    entry.org_id.as_deref(),
-                )
-                .await?;
+// the client modifies the db directly when it queues changes made while
+// offline, so the search index also needs rebuilding whenever the file
+// changes
+async fn ensure_search_index(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+) -> anyhow::Result<()> {
+    let modified = db_modified().await;
+    if state
+        .lock()
+        .await
+        .search_index
+        .as_ref()
+        .is_some_and(|index| index.is_current(modified))
+    {
+        return Ok(());
+    }
+    load_search_index(state).await
+}
 
-                return ssh_agent_lib::ssh_key::PrivateKey::from_openssh(
-                    private_key_plaintext,
-                )
-                .map_err(anyhow::Error::new);
-            }
-        }
+// pending changes are applied before indexing, since those are what the
+// client displays
+async fn load_search_index(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+) -> anyhow::Result<()> {
+    // read the modification time before loading, so that any changes made
+    // while we are loading will be picked up next time
+    let modified = db_modified().await;
+    let mut db = load_db().await?;
+    db.apply_pending();
+    state.lock().await.set_search_index(&db.entries, modified);
+    Ok(())
+}
+
+pub async fn confirm_ssh_sign(
+    state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    desc: &str,
+) -> anyhow::Result<()> {
+    let environment = {
+        let state = state.lock().await;
+        state
+            .unlock_environment
+            .clone()
+            .unwrap_or_else(|| state.last_environment().clone())
+    };
+    let confirmed = config_prompt()
+        .await?
+        .confirm(desc, "Allow", "Deny", &environment)
+        .await
+        .context("failed to confirm ssh key use")?;
+    if !confirmed {
+        return Err(anyhow::anyhow!("ssh key use was not confirmed"));
     }
 
-    Err(anyhow::anyhow!("No matching private key found"))
+    Ok(())
 }
@@ -3,19 +3,27 @@ use futures_util::StreamExt as _;
 
 pub struct Agent {
     timer_r: tokio::sync::mpsc::UnboundedReceiver<()>,
+    lifetime_r: tokio::sync::mpsc::UnboundedReceiver<()>,
     sync_timer_r: tokio::sync::mpsc::UnboundedReceiver<()>,
+    session_r: tokio::sync::mpsc::UnboundedReceiver<crate::session::Message>,
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
 }
 
 impl Agent {
     pub fn new(
         timer_r: tokio::sync::mpsc::UnboundedReceiver<()>,
+        lifetime_r: tokio::sync::mpsc::UnboundedReceiver<()>,
         sync_timer_r: tokio::sync::mpsc::UnboundedReceiver<()>,
+        session_r: tokio::sync::mpsc::UnboundedReceiver<
+            crate::session::Message,
+        >,
         state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
     ) -> Self {
         Self {
             timer_r,
+            lifetime_r,
             sync_timer_r,
+            session_r,
             state,
         }
     }
@@ -28,6 +36,7 @@ impl Agent {
             Request(std::io::Result<tokio::net::UnixStream>),
             Timeout(()),
             Sync(()),
+            Session(crate::session::Message),
         }
 
         let notifications = self
@@ -56,23 +65,41 @@ impl Agent {
             )
             .map(Event::Timeout)
             .boxed(),
+            tokio_stream::wrappers::UnboundedReceiverStream::new(
+                self.lifetime_r,
+            )
+            .map(Event::Timeout)
+            .boxed(),
             tokio_stream::wrappers::UnboundedReceiverStream::new(
                 self.sync_timer_r,
             )
             .map(Event::Sync)
             .boxed(),
+            tokio_stream::wrappers::UnboundedReceiverStream::new(
+                self.session_r,
+            )
+            .map(Event::Session)
+            .boxed(),
             notifications,
         ]);
         while let Some(event) = stream.next().await {
             match event {
                 Event::Request(res) => {
-                    let mut sock = crate::sock::Sock::new(
-                        res.context("failed to accept incoming connection")?,
-                    );
+                    let stream =
+                        res.context("failed to accept incoming connection")?;
+                    let peer = match crate::peer::Peer::new(&stream) {
+                        Ok(peer) => peer,
+                        Err(e) => {
+                            log::warn!("{e:#}");
+                            continue;
+                        }
+                    };
+                    let mut sock = crate::sock::Sock::new(stream);
                     let state = self.state.clone();
                     tokio::spawn(async move {
                         let res =
-                            handle_request(&mut sock, state.clone()).await;
+                            handle_request(&mut sock, state.clone(), &peer)
+                                .await;
                         if let Err(e) = res {
                             // unwrap is the only option here
                             sock.send(&rbw::protocol::Response::Error {
@@ -99,6 +126,13 @@ impl Agent {
                     });
                     self.state.lock().await.set_sync_timeout();
                 }
+                Event::Session(message) => {
+                    let mut state = self.state.lock().await;
+                    if !state.needs_unlock() {
+                        log::info!("locking due to {message}");
+                    }
+                    state.clear();
+                }
             }
         }
         Ok(())
@@ -108,6 +142,7 @@ impl Agent {
 async fn handle_request(
     sock: &mut crate::sock::Sock,
     state: std::sync::Arc<tokio::sync::Mutex<crate::state::State>>,
+    peer: &crate::peer::Peer,
 ) -> anyhow::Result<()> {
     let req = sock.recv().await?;
     let req = match req {
@@ -131,6 +166,11 @@ async fn handle_request(
             crate::actions::unlock(sock, state.clone(), &environment).await?;
             true
         }
+        rbw::protocol::Action::SetPin => {
+            crate::actions::set_pin(sock, state.clone(), &environment)
+                .await?;
+            true
+        }
         rbw::protocol::Action::CheckLock => {
             crate::actions::check_lock(sock, state.clone()).await?;
             false
@@ -148,33 +188,98 @@ async fn handle_request(
             entry_key,
             org_id,
         } => {
-            let cipherstring = cipherstring.clone();
-            let entry_key = entry_key.clone();
-            let org_id = org_id.clone();
-            crate::actions::decrypt(
-                sock,
+            let res = async {
+                crate::actions::authorize_client(
+                    state.clone(),
+                    &environment,
+                    peer,
+                )
+                .await?;
+                crate::actions::decrypt(
+                    sock,
+                    state.clone(),
+                    &environment,
+                    cipherstring,
+                    entry_key.as_deref(),
+                    org_id.as_deref(),
+                )
+                .await
+            }
+            .await;
+            let entry_id = state.lock().await.entry_id(cipherstring);
+            crate::actions::audit(
                 state.clone(),
-                &environment,
-                &cipherstring,
-                entry_key.as_deref(),
-                org_id.as_deref(),
+                peer,
+                action.name(),
+                entry_id,
+                &res,
             )
-            .await?;
+            .await;
+            res?;
             true
         }
         rbw::protocol::Action::Encrypt { plaintext, org_id } => {
-            crate::actions::encrypt(
+            let res = crate::actions::encrypt(
                 sock,
                 state.clone(),
                 plaintext,
                 org_id.as_deref(),
             )
-            .await?;
+            .await;
+            crate::actions::audit(
+                state.clone(),
+                peer,
+                action.name(),
+                None,
+                &res,
+            )
+            .await;
+            res?;
             true
         }
         rbw::protocol::Action::ClipboardStore { text } => {
-            crate::actions::clipboard_store(sock, state.clone(), text)
+            let res = async {
+                crate::actions::authorize_client(
+                    state.clone(),
+                    &environment,
+                    peer,
+                )
                 .await?;
+                crate::actions::clipboard_store(sock, state.clone(), text)
+                    .await
+            }
+            .await;
+            crate::actions::audit(
+                state.clone(),
+                peer,
+                action.name(),
+                None,
+                &res,
+            )
+            .await;
+            res?;
+            true
+        }
+        rbw::protocol::Action::Index => {
+            let res = async {
+                crate::actions::authorize_client(
+                    state.clone(),
+                    &environment,
+                    peer,
+                )
+                .await?;
+                crate::actions::index(sock, state.clone()).await
+            }
+            .await;
+            crate::actions::audit(
+                state.clone(),
+                peer,
+                action.name(),
+                None,
+                &res,
+            )
+            .await;
+            res?;
             true
         }
         rbw::protocol::Action::Quit => std::process::exit(0),
@@ -187,7 +292,7 @@ async fn handle_request(
     let mut state = state.lock().await;
     state.set_last_environment(environment);
     if set_timeout {
-        state.set_timeout();
+        state.set_timeout_for(action.name());
     }
 
     Ok(())
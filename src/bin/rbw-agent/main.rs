@@ -5,8 +5,13 @@ mod agent;
 mod daemon;
 mod debugger;
 mod notifications;
+mod peer;
+mod search_index;
+mod session;
 mod sock;
 mod ssh_agent;
+mod ssh_bind;
+mod ssh_keys;
 mod state;
 mod timeout;
 
@@ -24,7 +29,10 @@ async fn tokio_main(
         std::time::Duration::from_secs(config.lock_timeout);
     let sync_timeout_duration =
         std::time::Duration::from_secs(config.sync_interval);
+    let lifetime_duration =
+        std::time::Duration::from_secs(config.unlock_lifetime);
     let (timeout, timer_r) = crate::timeout::Timeout::new();
+    let (lifetime, lifetime_r) = crate::timeout::Timeout::new();
     let (sync_timeout, sync_timer_r) = crate::timeout::Timeout::new();
     if sync_timeout_duration > std::time::Duration::ZERO {
         sync_timeout.set(sync_timeout_duration);
@@ -36,11 +44,33 @@ async fn tokio_main(
             org_keys: None,
             timeout,
             timeout_duration,
+            timeout_exempt_actions: config
+                .lock_timeout_exempt_actions
+                .iter()
+                .cloned()
+                .collect(),
+            lifetime,
+            lifetime_duration,
+            lifetime_deadline: None,
+            session_keyring: config.session_keyring,
+            audit_log: config.audit_log,
+            client_policy: config.client_policy,
+            allowed_clients: config.allowed_clients.iter().cloned().collect(),
+            ssh_confirm: config.ssh_confirm,
+            ssh_refuse_rsa_sha1: config.ssh_refuse_rsa_sha1,
+            ssh_refuse_forwarded: config.ssh_refuse_forwarded,
             sync_timeout,
             sync_timeout_duration,
+            sync_lock: std::sync::Arc::new(tokio::sync::Mutex::new(())),
             notifications_handler,
             master_password_reprompt: std::collections::HashSet::new(),
             master_password_reprompt_initialized: false,
+            entry_ids: std::collections::HashMap::new(),
+            ssh_key_index: None,
+            search_index: None,
+            ssh_added_keys: vec![],
+            approved_clients: std::collections::HashSet::new(),
+            unlock_environment: None,
             last_environment: rbw::protocol::Environment::default(),
             #[cfg(feature = "clipboard")]
             clipboard: arboard::Clipboard::new()
@@ -50,8 +80,15 @@ async fn tokio_main(
                 .ok(),
         }));
 
-    let agent =
-        crate::agent::Agent::new(timer_r, sync_timer_r, state.clone());
+    let session_r = crate::session::subscribe(&config);
+
+    let agent = crate::agent::Agent::new(
+        timer_r,
+        lifetime_r,
+        sync_timer_r,
+        session_r,
+        state.clone(),
+    );
 
     let ssh_agent = crate::ssh_agent::SshAgent::new(state.clone());
 
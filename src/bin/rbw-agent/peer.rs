@@ -0,0 +1,189 @@
+use anyhow::Context as _;
+
+// information about the process on the other end of an agent connection
+#[derive(Debug, Clone, Default)]
+pub struct Peer {
+    pub pid: Option<i32>,
+    pub exe: Option<std::path::PathBuf>,
+}
+
+impl Peer {
+    pub fn new(sock: &tokio::net::UnixStream) -> anyhow::Result<Self> {
+        let cred = sock
+            .peer_cred()
+            .context("failed to get peer credentials for connection")?;
+        let uid = rustix::process::getuid().as_raw();
+        if cred.uid() != uid {
+            return Err(anyhow::anyhow!(
+                "rejecting connection from uid {} (expected {uid})",
+                cred.uid()
+            ));
+        }
+
+        let Some(pid) = cred.pid() else {
+            return Ok(Self::default());
+        };
+        let exe = process_exe(pid);
+
+        // most requests come through the rbw client binary, which isn't
+        // very interesting to allow or deny on its own, so in that case we
+        // look at whatever ran the client instead
+        if exe.is_some() && exe == rbw_client_exe() {
+            let (pid, exe) = client_process(pid, process_exe, parent_pid);
+            return Ok(Self { pid, exe });
+        }
+
+        Ok(Self {
+            pid: Some(pid),
+            exe,
+        })
+    }
+}
+
+// the process that is responsible for running the rbw client with the given
+// pid. shells, interpreters and other programs which just run whatever
+// command they are given can be made to run rbw by any process at all, so
+// they are skipped over rather than being treated as the client (otherwise
+// allowing bash would allow everything that can run bash -c).
+fn client_process(
+    pid: i32,
+    exe: impl Fn(i32) -> Option<std::path::PathBuf>,
+    parent: impl Fn(i32) -> Option<i32>,
+) -> (Option<i32>, Option<std::path::PathBuf>) {
+    let mut pid = pid;
+    // bounded, in case of races with pids being reused
+    for _ in 0..MAX_ANCESTORS {
+        let Some(ppid) = parent(pid).filter(|ppid| *ppid > 1) else {
+            break;
+        };
+        pid = ppid;
+        let Some(ppid_exe) = exe(pid) else {
+            return (Some(pid), None);
+        };
+        if !is_launcher(&ppid_exe) {
+            return (Some(pid), Some(ppid_exe));
+        }
+    }
+    // nothing identifiable ran the client
+    (Some(pid), None)
+}
+
+const MAX_ANCESTORS: usize = 16;
+
+fn is_launcher(exe: &std::path::Path) -> bool {
+    let Some(name) = exe.file_name().and_then(std::ffi::OsStr::to_str) else {
+        return false;
+    };
+    // versioned interpreters like python3.12 or perl5.38
+    let name =
+        name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    matches!(
+        name,
+        "sh" | "bash"
+            | "dash"
+            | "zsh"
+            | "fish"
+            | "ksh"
+            | "mksh"
+            | "csh"
+            | "tcsh"
+            | "nu"
+            | "busybox"
+            | "python"
+            | "perl"
+            | "ruby"
+            | "node"
+            | "lua"
+            | "php"
+            | "env"
+            | "nohup"
+            | "timeout"
+            | "xargs"
+            | "setsid"
+            | "sudo"
+            | "doas"
+            | "rbw"
+    )
+}
+
+impl std::fmt::Display for Peer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.exe, self.pid) {
+            (Some(exe), Some(pid)) => {
+                write!(f, "{} (pid {pid})", exe.display())
+            }
+            (None, Some(pid)) => write!(f, "unknown executable (pid {pid})"),
+            _ => write!(f, "unknown process"),
+        }
+    }
+}
+
+fn process_exe(pid: i32) -> Option<std::path::PathBuf> {
+    std::fs::read_link(format!("/proc/{pid}/exe")).ok()
+}
+
+fn parent_pid(pid: i32) -> Option<i32> {
+    parse_parent_pid(
+        &std::fs::read_to_string(format!("/proc/{pid}/stat")).ok()?,
+    )
+}
+
+fn parse_parent_pid(stat: &str) -> Option<i32> {
+    // the second field is the command name in parens, which can itself
+    // contain spaces or parens, so skip past the last closing paren. the
+    // fields after that are the state and then the parent pid.
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
+
+fn rbw_client_exe() -> Option<std::path::PathBuf> {
+    std::env::current_exe()
+        .ok()
+        .map(|exe| exe.with_file_name("rbw"))
+}
+
+#[test]
+fn test_client_process() {
+    let processes: std::collections::HashMap<i32, (i32, &str)> = [
+        (100, (1, "/usr/lib/systemd/systemd")),
+        (200, (100, "/usr/bin/kitty")),
+        (300, (200, "/usr/bin/bash")),
+        (400, (300, "/usr/bin/rbw")),
+        (500, (100, "/home/user/.local/bin/evil")),
+        (600, (500, "/usr/bin/sh")),
+        (700, (600, "/usr/bin/python3.12")),
+        (800, (700, "/usr/bin/rbw")),
+        (900, (1, "/usr/bin/bash")),
+        (1000, (900, "/usr/bin/rbw")),
+    ]
+    .into_iter()
+    .collect();
+    let client = |pid| {
+        client_process(
+            pid,
+            |pid| processes.get(&pid).map(|(_, exe)| (*exe).into()),
+            |pid| processes.get(&pid).map(|(ppid, _)| *ppid),
+        )
+    };
+
+    // an interactive shell is skipped, and the terminal is the client
+    assert_eq!(client(400), (Some(200), Some("/usr/bin/kitty".into())));
+    // running rbw via a shell or interpreter doesn't let a process take on
+    // the identity of that shell or interpreter
+    assert_eq!(
+        client(800),
+        (Some(500), Some("/home/user/.local/bin/evil".into()))
+    );
+    // only launchers all the way up
+    assert_eq!(client(1000), (Some(900), None));
+}
+
+#[test]
+fn test_parse_parent_pid() {
+    assert_eq!(parse_parent_pid("1234 (bash) S 1000 1234 1234"), Some(1000));
+    assert_eq!(
+        parse_parent_pid("1234 (a (weird) name) R 42 1234 1234"),
+        Some(42)
+    );
+    assert_eq!(parse_parent_pid("1234 (bash"), None);
+}
@@ -0,0 +1,112 @@
+// an in-memory index of the decrypted metadata for each entry in the vault
+// (names, usernames, folders, uris, notes and non-hidden fields), so that
+// the client can search and list entries without making a separate decrypt
+// request for every value. secret values (passwords, totp secrets, card
+// numbers, hidden fields, etc) are never included, and are still only
+// decrypted on request.
+pub struct Index {
+    entries: Vec<rbw::protocol::IndexEntry>,
+    // the modification time of the db file that this index was built from,
+    // so that we can tell when the client has changed it out from under us
+    modified: Option<std::time::SystemTime>,
+}
+
+impl Index {
+    pub fn build(
+        state: &crate::state::State,
+        entries: &[rbw::db::Entry],
+        modified: Option<std::time::SystemTime>,
+    ) -> Self {
+        let entries = entries
+            .iter()
+            .filter_map(|entry| match index_entry(state, entry) {
+                Ok(entry) => Some(entry),
+                Err(e) => {
+                    log::warn!("failed to index entry {}: {e:#}", entry.id);
+                    None
+                }
+            })
+            .collect();
+
+        Self { entries, modified }
+    }
+
+    pub fn entries(&self) -> &[rbw::protocol::IndexEntry] {
+        &self.entries
+    }
+
+    pub fn is_current(
+        &self,
+        modified: Option<std::time::SystemTime>,
+    ) -> bool {
+        modified.is_some() && self.modified == modified
+    }
+}
+
+fn index_entry(
+    state: &crate::state::State,
+    entry: &rbw::db::Entry,
+) -> anyhow::Result<rbw::protocol::IndexEntry> {
+    let entry_key = entry.key.as_deref();
+    let org_id = entry.org_id.as_deref();
+    // values other than the name are optional, so failing to decrypt one
+    // of them shouldn't make the whole entry unsearchable
+    let decrypt = |name: &str, cipherstring: Option<&str>| {
+        cipherstring.and_then(|cipherstring| {
+            state
+                .decrypt(cipherstring, entry_key, org_id)
+                .inspect_err(|e| {
+                    log::warn!(
+                        "failed to decrypt {name} for entry {}: {e:#}",
+                        entry.id
+                    );
+                })
+                .ok()
+        })
+    };
+
+    let name = state.decrypt(&entry.name, entry_key, org_id)?;
+    // folder names are always encrypted with the user's key, since folders
+    // are local to a specific user's vault
+    let folder = entry
+        .folder
+        .as_ref()
+        .map(|folder| state.decrypt(folder, None, None))
+        .transpose()?;
+    let (user, uris) = match &entry.data {
+        rbw::db::EntryData::Login { username, uris, .. } => (
+            decrypt("username", username.as_deref()),
+            uris.iter()
+                .filter_map(|uri| {
+                    decrypt("uri", Some(&uri.uri))
+                        .map(|decrypted| (decrypted, uri.match_type))
+                })
+                .collect(),
+        ),
+        _ => (None, vec![]),
+    };
+    let fields = entry
+        .fields
+        .iter()
+        .map(|field| rbw::protocol::IndexField {
+            name: decrypt("field name", field.name.as_deref()),
+            value: if field.ty == Some(rbw::api::FieldType::Hidden) {
+                None
+            } else {
+                decrypt("field", field.value.as_deref())
+            },
+        })
+        .collect();
+    let notes = decrypt("notes", entry.notes.as_deref());
+
+    Ok(rbw::protocol::IndexEntry {
+        id: entry.id.clone(),
+        entry_type: entry.data.type_name().to_string(),
+        folder,
+        name,
+        user,
+        uris,
+        fields,
+        notes,
+    })
+}
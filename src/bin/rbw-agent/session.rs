@@ -0,0 +1,280 @@
+use anyhow::Context as _;
+use futures_util::StreamExt as _;
+
+const LOGIN1_SERVICE: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_MANAGER_INTERFACE: &str = "org.freedesktop.login1.Manager";
+const LOGIN1_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const SCREENSAVER_INTERFACES: &[&str] =
+    &["org.freedesktop.ScreenSaver", "org.gnome.ScreenSaver"];
+
+#[derive(Clone, Copy, Debug)]
+pub enum Message {
+    Suspend,
+    SessionLock,
+    ScreenSaver,
+    Logout,
+}
+
+impl std::fmt::Display for Message {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Suspend => write!(f, "system suspend"),
+            Self::SessionLock => write!(f, "session lock"),
+            Self::ScreenSaver => write!(f, "screen saver activation"),
+            Self::Logout => write!(f, "session logout"),
+        }
+    }
+}
+
+// returns a channel which receives a message whenever one of the lock
+// triggers enabled in the config fires. failing to connect to the bus (for
+// instance, on systems without logind) is not fatal, it just means that
+// those triggers will never fire.
+pub fn subscribe(
+    config: &rbw::config::Config,
+) -> tokio::sync::mpsc::UnboundedReceiver<Message> {
+    let (w, r) = tokio::sync::mpsc::unbounded_channel();
+
+    if config.lock_on_suspend
+        || config.lock_on_session_lock
+        || config.lock_on_logout
+    {
+        let w = w.clone();
+        let suspend = config.lock_on_suspend;
+        let session_lock = config.lock_on_session_lock;
+        let logout = config.lock_on_logout;
+        tokio::spawn(async move {
+            if let Err(e) =
+                watch_logind(w, suspend, session_lock, logout).await
+            {
+                log::warn!("failed to watch logind signals: {e:#}");
+            }
+        });
+    }
+
+    if config.lock_on_screensaver {
+        tokio::spawn(async move {
+            if let Err(e) = watch_screensaver(w).await {
+                log::warn!("failed to watch screen saver signals: {e:#}");
+            }
+        });
+    }
+
+    r
+}
+
+async fn watch_logind(
+    w: tokio::sync::mpsc::UnboundedSender<Message>,
+    suspend: bool,
+    session_lock: bool,
+    logout: bool,
+) -> anyhow::Result<()> {
+    let conn = zbus::Connection::system()
+        .await
+        .context("failed to connect to the system bus")?;
+
+    let mut rules = vec![];
+    if suspend {
+        rules.push(login1_signal(
+            LOGIN1_MANAGER_INTERFACE,
+            "PrepareForSleep",
+        )?);
+    }
+    if session_lock {
+        rules.push(login1_signal(LOGIN1_SESSION_INTERFACE, "Lock")?);
+    }
+    if logout {
+        rules.push(login1_signal(LOGIN1_MANAGER_INTERFACE, "SessionNew")?);
+        rules
+            .push(login1_signal(LOGIN1_MANAGER_INTERFACE, "SessionRemoved")?);
+    }
+    let mut stream = subscribe_rules(&conn, rules).await?;
+
+    // logind broadcasts session signals for every session on the system, so
+    // we need to keep track of which ones belong to us
+    let mut sessions = std::collections::HashSet::new();
+    refresh_sessions(&conn, &mut sessions).await;
+
+    // errors in handling individual messages are only logged, since
+    // returning would silently disable locking for the rest of the
+    // agent's lifetime
+    while let Some(msg) = stream.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("failed to read message from system bus: {e}");
+                continue;
+            }
+        };
+        let header = msg.header();
+        let Some(member) = header.member() else {
+            continue;
+        };
+        match member.as_str() {
+            "PrepareForSleep" => {
+                match msg.body().deserialize::<bool>() {
+                    // the signal is sent with true before suspending and
+                    // with false after resuming
+                    Ok(true) => send(&w, Message::Suspend),
+                    Ok(false) => {}
+                    Err(e) => {
+                        log::warn!("failed to parse PrepareForSleep: {e}");
+                    }
+                }
+            }
+            "Lock" => {
+                let Some(path) = header.path() else {
+                    continue;
+                };
+                if !sessions.contains(path.as_str()) {
+                    refresh_sessions(&conn, &mut sessions).await;
+                }
+                if sessions.contains(path.as_str()) {
+                    send(&w, Message::SessionLock);
+                }
+            }
+            "SessionNew" => {
+                refresh_sessions(&conn, &mut sessions).await;
+            }
+            "SessionRemoved" => {
+                let body: zbus::Result<(
+                    String,
+                    zbus::zvariant::OwnedObjectPath,
+                )> = msg.body().deserialize();
+                let path = match body {
+                    Ok((_, path)) => path,
+                    Err(e) => {
+                        log::warn!("failed to parse SessionRemoved: {e}");
+                        continue;
+                    }
+                };
+                if sessions.remove(path.as_str()) {
+                    send(&w, Message::Logout);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+async fn watch_screensaver(
+    w: tokio::sync::mpsc::UnboundedSender<Message>,
+) -> anyhow::Result<()> {
+    let conn = zbus::Connection::session()
+        .await
+        .context("failed to connect to the session bus")?;
+
+    let rules = SCREENSAVER_INTERFACES
+        .iter()
+        .map(|interface| {
+            Ok(zbus::MatchRule::builder()
+                .msg_type(zbus::message::Type::Signal)
+                .interface(*interface)?
+                .member("ActiveChanged")?
+                .build())
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let mut stream = subscribe_rules(&conn, rules).await?;
+
+    while let Some(msg) = stream.next().await {
+        let msg = match msg {
+            Ok(msg) => msg,
+            Err(e) => {
+                log::warn!("failed to read message from session bus: {e}");
+                continue;
+            }
+        };
+        match msg.body().deserialize::<bool>() {
+            Ok(true) => send(&w, Message::ScreenSaver),
+            Ok(false) => {}
+            Err(e) => log::warn!("failed to parse ActiveChanged: {e}"),
+        }
+    }
+
+    Ok(())
+}
+
+fn login1_signal(
+    interface: &'static str,
+    member: &'static str,
+) -> anyhow::Result<zbus::MatchRule<'static>> {
+    let builder = zbus::MatchRule::builder()
+        .msg_type(zbus::message::Type::Signal)
+        .sender(LOGIN1_SERVICE)?
+        .interface(interface)?
+        .member(member)?;
+    // session signals are sent from the individual session objects
+    let builder = if interface == LOGIN1_MANAGER_INTERFACE {
+        builder.path(LOGIN1_PATH)?
+    } else {
+        builder.path_namespace(LOGIN1_PATH)?
+    };
+    Ok(builder.build())
+}
+
+async fn subscribe_rules(
+    conn: &zbus::Connection,
+    rules: Vec<zbus::MatchRule<'static>>,
+) -> anyhow::Result<futures_util::stream::SelectAll<zbus::MessageStream>> {
+    let mut streams = vec![];
+    for rule in rules {
+        streams.push(
+            zbus::MessageStream::for_match_rule(rule, conn, None)
+                .await
+                .context("failed to add match rule")?,
+        );
+    }
+    Ok(futures_util::stream::select_all(streams))
+}
+
+// keeps the previous set of sessions if logind can't be reached, so that a
+// transient failure doesn't stop us from recognizing sessions we already
+// know about
+async fn refresh_sessions(
+    conn: &zbus::Connection,
+    sessions: &mut std::collections::HashSet<String>,
+) {
+    match user_sessions(conn).await {
+        Ok(new) => *sessions = new,
+        Err(e) => log::warn!("{e:#}"),
+    }
+}
+
+async fn user_sessions(
+    conn: &zbus::Connection,
+) -> anyhow::Result<std::collections::HashSet<String>> {
+    let uid = rustix::process::getuid().as_raw();
+    let reply = conn
+        .call_method(
+            Some(LOGIN1_SERVICE),
+            LOGIN1_PATH,
+            Some(LOGIN1_MANAGER_INTERFACE),
+            "ListSessions",
+            &(),
+        )
+        .await
+        .context("failed to list logind sessions")?;
+    let sessions: Vec<(
+        String,
+        u32,
+        String,
+        String,
+        zbus::zvariant::OwnedObjectPath,
+    )> = reply
+        .body()
+        .deserialize()
+        .context("failed to parse logind session list")?;
+    Ok(sessions
+        .into_iter()
+        .filter(|(_, session_uid, _, _, _)| *session_uid == uid)
+        .map(|(_, _, _, _, path)| path.as_str().to_string())
+        .collect())
+}
+
+fn send(w: &tokio::sync::mpsc::UnboundedSender<Message>, message: Message) {
+    // the receiver only goes away when the agent is shutting down
+    let _ = w.send(message);
+}
@@ -0,0 +1,533 @@
+use ssh_agent_lib::ssh_encoding::Decode as _;
+
+// the same limit that openssh uses
+const MAX_BINDINGS: usize = 16;
+
+const SSH_MSG_USERAUTH_REQUEST: u8 = 50;
+
+// a session that an ssh agent connection has been bound to via the
+// session-bind@openssh.com extension. see PROTOCOL.agent in the openssh
+// source for details.
+struct Binding {
+    host_key: ssh_agent_lib::ssh_key::public::KeyData,
+    session_id: Vec<u8>,
+    forwarded: bool,
+}
+
+// the sessions bound to a single ssh agent connection, in order. the last
+// binding is the hop that is currently trying to use the agent, and any
+// previous bindings are the hops that the agent was forwarded through.
+#[derive(Default)]
+pub struct Bindings {
+    bindings: Vec<Binding>,
+    attempted: bool,
+}
+
+struct UserAuthRequest {
+    session_id: Vec<u8>,
+    user: String,
+    host_key: Option<ssh_agent_lib::ssh_key::public::KeyData>,
+}
+
+impl Bindings {
+    pub fn bind(
+        &mut self,
+        bind: ssh_agent_lib::proto::extension::SessionBind,
+    ) -> anyhow::Result<()> {
+        self.attempted = true;
+        bind.verify_signature().map_err(|e| {
+            anyhow::anyhow!("failed to verify session bind signature: {e}")
+        })?;
+
+        for binding in &self.bindings {
+            if !binding.forwarded {
+                return Err(anyhow::anyhow!(
+                    "connection was already bound for authentication"
+                ));
+            }
+            if binding.session_id == bind.session_id {
+                if binding.host_key == bind.host_key {
+                    return Ok(());
+                }
+                return Err(anyhow::anyhow!(
+                    "session id was already bound to a different host key"
+                ));
+            }
+        }
+        if self.bindings.len() >= MAX_BINDINGS {
+            return Err(anyhow::anyhow!("too many session bindings"));
+        }
+
+        self.bindings.push(Binding {
+            host_key: bind.host_key,
+            session_id: bind.session_id,
+            forwarded: bind.is_forwarding,
+        });
+        Ok(())
+    }
+
+    // whether this connection comes from an agent forwarded to another host
+    pub fn is_forwarded(&self) -> bool {
+        self.bindings.iter().any(|binding| binding.forwarded)
+    }
+
+    // whether a key with the given destination constraints can be used on
+    // this connection, either to be listed (when user is None) or to
+    // authenticate as the given user
+    pub fn permits(
+        &self,
+        destinations: &[ssh_agent_lib::proto::extension::DestinationConstraint],
+        user: Option<&str>,
+    ) -> bool {
+        if destinations.is_empty() {
+            return true;
+        }
+        if self.attempted && self.bindings.is_empty() {
+            return false;
+        }
+        // unbound connections are local use
+        let Some(last) = self.bindings.last() else {
+            return true;
+        };
+
+        // every hop along the way needs to be allowed by one of the
+        // constraints
+        let mut from = None;
+        for (i, binding) in self.bindings.iter().enumerate() {
+            let user = if i == self.bindings.len() - 1 {
+                if binding.forwarded && user.is_some() {
+                    return false;
+                }
+                user
+            } else {
+                if !binding.forwarded {
+                    return false;
+                }
+                None
+            };
+            if !permitted_by(
+                destinations,
+                from,
+                Some(&binding.host_key),
+                user,
+            ) {
+                return false;
+            }
+            from = Some(&binding.host_key);
+        }
+
+        // keys which are allowed to authenticate to a host but not to be
+        // used from it shouldn't be visible to that host
+        if last.forwarded
+            && user.is_none()
+            && !permitted_by(destinations, Some(&last.host_key), None, None)
+        {
+            return false;
+        }
+
+        true
+    }
+
+    // destination constrained keys can only be used to sign user
+    // authentication requests for the session most recently bound to this
+    // connection
+    pub fn check_sign(
+        &self,
+        destinations: &[ssh_agent_lib::proto::extension::DestinationConstraint],
+        key: &ssh_agent_lib::ssh_key::public::KeyData,
+        data: &[u8],
+    ) -> anyhow::Result<()> {
+        if destinations.is_empty() {
+            return Ok(());
+        }
+        let Some(last) = self.bindings.last() else {
+            return Err(anyhow::anyhow!(
+                "refusing to use a destination constrained key on an \
+                unbound connection"
+            ));
+        };
+        let request = parse_userauth_request(data, key).ok_or_else(|| {
+            anyhow::anyhow!(
+                "refusing to use a destination constrained key to sign \
+                something other than a user authentication request"
+            )
+        })?;
+        if !self.permits(destinations, Some(&request.user)) {
+            return Err(anyhow::anyhow!(
+                "key is not permitted to authenticate as {} on this \
+                connection",
+                request.user
+            ));
+        }
+        if request.session_id != last.session_id {
+            return Err(anyhow::anyhow!(
+                "signature request is not for the most recently bound \
+                session"
+            ));
+        }
+        match request.host_key {
+            None if self.bindings.len() > 1 => Err(anyhow::anyhow!(
+                "signature request on a forwarded connection has no host key"
+            )),
+            Some(host_key) if host_key != last.host_key => {
+                Err(anyhow::anyhow!(
+                    "host key in signature request doesn't match the most \
+                    recently bound session"
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+fn permitted_by(
+    destinations: &[ssh_agent_lib::proto::extension::DestinationConstraint],
+    from: Option<&ssh_agent_lib::ssh_key::public::KeyData>,
+    to: Option<&ssh_agent_lib::ssh_key::public::KeyData>,
+    user: Option<&str>,
+) -> bool {
+    destinations.iter().any(|destination| {
+        let from_matches = from.map_or_else(
+            // the first hop must be from the local host
+            || {
+                destination.from.hostname.is_empty()
+                    && destination.from.keys.is_empty()
+            },
+            |from| matches_hop(from, &destination.from),
+        );
+        from_matches
+            && to.is_none_or(|to| matches_hop(to, &destination.to))
+            && user.is_none_or(|user| {
+                destination.to.username.is_empty()
+                    || crate::ssh_keys::glob_match(
+                        destination.to.username.as_bytes(),
+                        user.as_bytes(),
+                    )
+            })
+    })
+}
+
+// host certificates aren't supported, since ssh-agent-lib can't decode them
+// in session bind requests, so this only matches against plain host keys
+fn matches_hop(
+    key: &ssh_agent_lib::ssh_key::public::KeyData,
+    hop: &ssh_agent_lib::proto::extension::HostTuple,
+) -> bool {
+    hop.keys
+        .iter()
+        .any(|spec| !spec.is_ca && spec.keyblob == *key)
+}
+
+// parses a SSH_MSG_USERAUTH_REQUEST for publickey authentication with the
+// given key, as described in RFC 4252 section 7 (and PROTOCOL in the openssh
+// source for the hostbound variant)
+fn parse_userauth_request(
+    data: &[u8],
+    key: &ssh_agent_lib::ssh_key::public::KeyData,
+) -> Option<UserAuthRequest> {
+    let mut rest = data;
+    let session_id = crate::ssh_agent::read_string(&mut rest)?.to_vec();
+    let (&SSH_MSG_USERAUTH_REQUEST, mut rest) = rest.split_first()? else {
+        return None;
+    };
+    let user = std::str::from_utf8(crate::ssh_agent::read_string(&mut rest)?)
+        .ok()?
+        .to_string();
+    if crate::ssh_agent::read_string(&mut rest)? != b"ssh-connection" {
+        return None;
+    }
+    let hostbound = match crate::ssh_agent::read_string(&mut rest)? {
+        b"publickey" => false,
+        b"publickey-hostbound-v00@openssh.com" => true,
+        _ => return None,
+    };
+    let (&has_signature, mut rest) = rest.split_first()?;
+    if has_signature == 0 {
+        return None;
+    }
+    // algorithm name
+    crate::ssh_agent::read_string(&mut rest)?;
+    let mut key_blob = crate::ssh_agent::read_string(&mut rest)?;
+    if ssh_agent_lib::ssh_key::public::KeyData::decode(&mut key_blob).ok()?
+        != *key
+    {
+        return None;
+    }
+    let host_key = if hostbound {
+        let mut host_key_blob = crate::ssh_agent::read_string(&mut rest)?;
+        Some(
+            ssh_agent_lib::ssh_key::public::KeyData::decode(
+                &mut host_key_blob,
+            )
+            .ok()?,
+        )
+    } else {
+        None
+    };
+    if !rest.is_empty() {
+        return None;
+    }
+
+    Some(UserAuthRequest {
+        session_id,
+        user,
+        host_key,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ssh_agent_lib::ssh_encoding::Encode as _;
+
+    fn random_key() -> ssh_agent_lib::ssh_key::PrivateKey {
+        ssh_agent_lib::ssh_key::PrivateKey::random(
+            &mut rand_8::rngs::OsRng,
+            ssh_agent_lib::ssh_key::Algorithm::Ed25519,
+        )
+        .unwrap()
+    }
+
+    fn session_bind(
+        host_key: &ssh_agent_lib::ssh_key::PrivateKey,
+        session_id: &[u8],
+        is_forwarding: bool,
+    ) -> ssh_agent_lib::proto::extension::SessionBind {
+        ssh_agent_lib::proto::extension::SessionBind {
+            host_key: host_key.public_key().key_data().clone(),
+            session_id: session_id.to_vec(),
+            signature: signature::Signer::sign(host_key, session_id),
+            is_forwarding,
+        }
+    }
+
+    fn hop(
+        hostname: &str,
+        host_key: Option<&ssh_agent_lib::ssh_key::PrivateKey>,
+    ) -> ssh_agent_lib::proto::extension::HostTuple {
+        ssh_agent_lib::proto::extension::HostTuple {
+            username: String::new(),
+            hostname: hostname.to_string(),
+            keys: host_key
+                .into_iter()
+                .map(|key| ssh_agent_lib::proto::extension::KeySpec {
+                    keyblob: key.public_key().key_data().clone(),
+                    is_ca: false,
+                })
+                .collect(),
+        }
+    }
+
+    fn userauth(
+        session_id: &[u8],
+        user: &str,
+        key: &ssh_agent_lib::ssh_key::PrivateKey,
+        host_key: Option<&ssh_agent_lib::ssh_key::PrivateKey>,
+    ) -> Vec<u8> {
+        let mut data = vec![];
+        session_id.encode(&mut data).unwrap();
+        data.push(SSH_MSG_USERAUTH_REQUEST);
+        user.encode(&mut data).unwrap();
+        "ssh-connection".encode(&mut data).unwrap();
+        if host_key.is_some() {
+            "publickey-hostbound-v00@openssh.com"
+                .encode(&mut data)
+                .unwrap();
+        } else {
+            "publickey".encode(&mut data).unwrap();
+        }
+        data.push(1);
+        "ssh-ed25519".encode(&mut data).unwrap();
+        key.public_key()
+            .to_bytes()
+            .unwrap()
+            .encode(&mut data)
+            .unwrap();
+        if let Some(host_key) = host_key {
+            host_key
+                .public_key()
+                .to_bytes()
+                .unwrap()
+                .encode(&mut data)
+                .unwrap();
+        }
+        data
+    }
+
+    #[test]
+    fn test_direct() {
+        let key = random_key();
+        let host_a = random_key();
+        let host_b = random_key();
+        let to_a = [ssh_agent_lib::proto::extension::DestinationConstraint {
+            from: hop("", None),
+            to: hop("a", Some(&host_a)),
+        }];
+
+        // unbound connections can list keys, but not sign with them
+        let mut bindings = Bindings::default();
+        assert!(bindings.permits(&to_a, None));
+        assert!(bindings
+            .check_sign(
+                &to_a,
+                key.public_key().key_data(),
+                &userauth(b"a", "user", &key, None),
+            )
+            .is_err());
+        assert!(bindings
+            .check_sign(
+                &[],
+                key.public_key().key_data(),
+                &userauth(b"a", "user", &key, None),
+            )
+            .is_ok());
+
+        // a forged binding is rejected, and poisons the connection
+        let mut forged = session_bind(&host_a, b"a", false);
+        forged.session_id = b"b".to_vec();
+        assert!(bindings.bind(forged).is_err());
+        assert!(!bindings.permits(&to_a, None));
+
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_a, b"a", false)).unwrap();
+        assert!(!bindings.is_forwarded());
+        assert!(bindings.permits(&to_a, None));
+        assert!(bindings.permits(&to_a, Some("user")));
+        bindings
+            .check_sign(
+                &to_a,
+                key.public_key().key_data(),
+                &userauth(b"a", "user", &key, Some(&host_a)),
+            )
+            .unwrap();
+        // signing for a different session
+        assert!(bindings
+            .check_sign(
+                &to_a,
+                key.public_key().key_data(),
+                &userauth(b"b", "user", &key, Some(&host_a)),
+            )
+            .is_err());
+        // signing for a different host
+        assert!(bindings
+            .check_sign(
+                &to_a,
+                key.public_key().key_data(),
+                &userauth(b"a", "user", &key, Some(&host_b)),
+            )
+            .is_err());
+        // signing something other than a userauth request
+        assert!(bindings
+            .check_sign(&to_a, key.public_key().key_data(), b"SSHSIG")
+            .is_err());
+
+        let mut to_b_as_root = hop("b", Some(&host_b));
+        to_b_as_root.username = "root".to_string();
+        let to_b = [ssh_agent_lib::proto::extension::DestinationConstraint {
+            from: hop("", None),
+            to: to_b_as_root,
+        }];
+        assert!(!bindings.permits(&to_b, None));
+
+        // connections bound for authentication can't be bound again
+        assert!(bindings.bind(session_bind(&host_b, b"b", false)).is_err());
+
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_b, b"b", false)).unwrap();
+        assert!(bindings.permits(&to_b, None));
+        assert!(bindings.permits(&to_b, Some("root")));
+        assert!(!bindings.permits(&to_b, Some("user")));
+        assert!(!bindings.permits(&to_a, None));
+    }
+
+    #[test]
+    fn test_forwarded() {
+        let host_a = random_key();
+        let host_b = random_key();
+        let host_c = random_key();
+        let to_a = ssh_agent_lib::proto::extension::DestinationConstraint {
+            from: hop("", None),
+            to: hop("a", Some(&host_a)),
+        };
+        let a_to_b = ssh_agent_lib::proto::extension::DestinationConstraint {
+            from: hop("a", Some(&host_a)),
+            to: hop("b", Some(&host_b)),
+        };
+
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_a, b"a", true)).unwrap();
+        assert!(bindings.is_forwarded());
+        // keys that can't be used beyond the forwarded host aren't visible
+        // to it
+        assert!(!bindings.permits(std::slice::from_ref(&to_a), None));
+        assert!(bindings.permits(&[to_a.clone(), a_to_b.clone()], None));
+        // and keys can't be used to sign on a forwarding hop
+        assert!(!bindings.permits(&[to_a.clone(), a_to_b.clone()], Some("u")));
+
+        bindings.bind(session_bind(&host_b, b"b", false)).unwrap();
+        assert!(bindings.permits(&[to_a.clone(), a_to_b.clone()], Some("u")));
+        assert!(!bindings.permits(std::slice::from_ref(&a_to_b), Some("u")));
+
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_a, b"a", true)).unwrap();
+        bindings.bind(session_bind(&host_c, b"c", false)).unwrap();
+        assert!(!bindings.permits(&[to_a, a_to_b], Some("u")));
+    }
+
+    #[test]
+    fn test_vault_destination() {
+        let key = random_key();
+        let host_a = random_key();
+        let host_b = random_key();
+        let public = |key: &ssh_agent_lib::ssh_key::PrivateKey| {
+            key.public_key().to_openssh().unwrap()
+        };
+        let destinations = [
+            crate::ssh_keys::parse_destination(&format!(
+                "a {}",
+                public(&host_a)
+            ))
+            .unwrap(),
+            crate::ssh_keys::parse_destination(&format!(
+                "a {} > root@b {}",
+                public(&host_a),
+                public(&host_b)
+            ))
+            .unwrap(),
+        ];
+        assert_eq!(destinations[1].to.username, "root");
+        assert_eq!(destinations[1].to.hostname, "b");
+
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_a, b"a", true)).unwrap();
+        bindings.bind(session_bind(&host_b, b"b", false)).unwrap();
+        assert!(bindings.permits(&destinations, None));
+        bindings
+            .check_sign(
+                &destinations,
+                key.public_key().key_data(),
+                &userauth(b"b", "root", &key, Some(&host_b)),
+            )
+            .unwrap();
+        assert!(bindings
+            .check_sign(
+                &destinations,
+                key.public_key().key_data(),
+                &userauth(b"b", "user", &key, Some(&host_b)),
+            )
+            .is_err());
+
+        // forwarded through a host that isn't listed
+        let mut bindings = Bindings::default();
+        bindings.bind(session_bind(&host_b, b"b", true)).unwrap();
+        assert!(!bindings.permits(&destinations, None));
+
+        assert!(crate::ssh_keys::parse_destination("a").is_err());
+        assert!(crate::ssh_keys::parse_destination(&format!(
+            "user@a {} > b {}",
+            public(&host_a),
+            public(&host_b)
+        ))
+        .is_err());
+    }
+}
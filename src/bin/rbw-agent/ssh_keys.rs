@@ -0,0 +1,586 @@
+// an in-memory index of the ssh keys in the vault, so that we don't have to
+// load the db and decrypt every public key on every ssh agent request. this
+// only ever contains the public parts of the keys (and some metadata) - the
+// private keys are kept encrypted, and are only decrypted when they are
+// actually needed for signing.
+pub struct Key {
+    pub entry_id: String,
+    pub name: String,
+    pub folder: Option<String>,
+    pub hosts: Vec<String>,
+    pub public_key: ssh_agent_lib::ssh_key::PublicKey,
+    pub certificates: Vec<ssh_agent_lib::ssh_key::Certificate>,
+    pub private_key: Option<String>,
+    pub entry_key: Option<String>,
+    pub org_id: Option<String>,
+    pub confirm: bool,
+    // the hosts this key may be used to authenticate to, in the same way as
+    // keys added with ssh-add -h. empty if the key is unrestricted.
+    pub destinations:
+        Vec<ssh_agent_lib::proto::extension::DestinationConstraint>,
+}
+
+// a key added to the agent at runtime (via ssh-add) rather than stored in
+// the vault. these are only ever kept in memory, and are forgotten whenever
+// the agent is locked.
+pub struct AddedKey {
+    pub private_key: ssh_agent_lib::ssh_key::PrivateKey,
+    pub certificate: Option<ssh_agent_lib::ssh_key::Certificate>,
+    pub confirm: bool,
+    pub expires: Option<std::time::Instant>,
+    // the hosts this key may be used to authenticate to, as set by
+    // ssh-add -h. empty if the key is unrestricted.
+    pub destinations:
+        Vec<ssh_agent_lib::proto::extension::DestinationConstraint>,
+}
+
+// an identity to offer to ssh clients: either a plain public key, or a
+// certificate for one of our keys
+pub struct Identity {
+    pub public_key: ssh_agent_lib::ssh_key::public::KeyData,
+    pub certificate: Option<ssh_agent_lib::ssh_key::Certificate>,
+    pub comment: String,
+}
+
+pub struct Index {
+    keys: Vec<Key>,
+    fingerprints: std::collections::HashMap<String, usize>,
+}
+
+// custom field which can be set on ssh key entries to require confirmation
+// for each use of the key, like ssh-add -c
+const CONFIRM_FIELD: &str = "rbw-ssh-confirm";
+// custom field which can be set on ssh key entries to only offer the key
+// when connecting to hosts matching one of the listed patterns (in the same
+// format as the Host directive in ssh_config)
+const HOSTS_FIELD: &str = "rbw-ssh-hosts";
+// custom field (which can be given multiple times) containing an openssh
+// certificate for the key, which will be offered alongside the key itself
+const CERTIFICATE_FIELD: &str = "rbw-ssh-certificate";
+// custom field (which can be given multiple times) restricting the key to
+// the given destination, like ssh-add -h. see parse_destination for the
+// format.
+const DESTINATION_FIELD: &str = "rbw-ssh-destination";
+
+impl Index {
+    pub fn build(
+        state: &crate::state::State,
+        entries: &[rbw::db::Entry],
+    ) -> Self {
+        let mut keys = vec![];
+        for entry in entries {
+            match Key::new(state, entry) {
+                Ok(Some(key)) => keys.push(key),
+                Ok(None) => {}
+                Err(e) => {
+                    log::warn!(
+                        "failed to load ssh key from entry {}: {e:#}",
+                        entry.id
+                    );
+                }
+            }
+        }
+
+        let fingerprints = keys
+            .iter()
+            .enumerate()
+            .map(|(i, key)| (fingerprint(&key.public_key), i))
+            .collect();
+
+        Self { keys, fingerprints }
+    }
+
+    // the keys to present in response to a request for identities. keys
+    // which are restricted to specific hosts are only offered when we know
+    // that we are connecting to a matching host, and are offered before
+    // unrestricted keys, so that the limit doesn't cut them off.
+    pub fn offered(
+        &self,
+        folder: Option<&str>,
+        host: Option<&str>,
+        limit: Option<usize>,
+        bindings: &crate::ssh_bind::Bindings,
+    ) -> Vec<&Key> {
+        let (mut matching, unrestricted): (Vec<_>, Vec<_>) = self
+            .keys
+            .iter()
+            .filter(|key| key.in_folder(folder))
+            .filter(|key| bindings.permits(&key.destinations, None))
+            .filter(|key| key.permits_host(host))
+            .partition(|key| !key.hosts.is_empty());
+        matching.extend(unrestricted);
+        if let Some(limit) = limit {
+            matching.truncate(limit);
+        }
+        matching
+    }
+
+    // host restrictions are also checked here, since a client could ask
+    // for a signature from a key that it already knows about without
+    // requesting the list of identities first
+    pub fn find(
+        &self,
+        public_key: &ssh_agent_lib::ssh_key::PublicKey,
+        folder: Option<&str>,
+        host: Option<&str>,
+    ) -> Option<&Key> {
+        self.fingerprints
+            .get(&fingerprint(public_key))
+            .and_then(|i| self.keys.get(*i))
+            .filter(|key| key.in_folder(folder))
+            .filter(|key| key.permits_host(host))
+    }
+}
+
+impl Key {
+    fn new(
+        state: &crate::state::State,
+        entry: &rbw::db::Entry,
+    ) -> anyhow::Result<Option<Self>> {
+        let rbw::db::EntryData::SshKey {
+            private_key,
+            public_key: Some(public_key),
+            ..
+        } = &entry.data
+        else {
+            return Ok(None);
+        };
+
+        let entry_key = entry.key.as_deref();
+        let org_id = entry.org_id.as_deref();
+        let public_key = ssh_agent_lib::ssh_key::PublicKey::from_openssh(
+            &state.decrypt(public_key, entry_key, org_id)?,
+        )?;
+        let name = state.decrypt(&entry.name, entry_key, org_id)?;
+        // folder names are always encrypted with the user's key, since
+        // folders are local to a specific user's vault
+        let folder = entry
+            .folder
+            .as_ref()
+            .map(|folder| state.decrypt(folder, None, None))
+            .transpose()?;
+
+        let mut confirm = false;
+        let mut hosts = vec![];
+        let mut certificates = vec![];
+        let mut destinations = vec![];
+        for field in &entry.fields {
+            let (Some(field_name), Some(value)) = (&field.name, &field.value)
+            else {
+                continue;
+            };
+            match state.decrypt(field_name, entry_key, org_id)?.as_str() {
+                CONFIRM_FIELD => {
+                    confirm =
+                        is_truthy(&state.decrypt(value, entry_key, org_id)?);
+                }
+                HOSTS_FIELD => {
+                    hosts.extend(
+                        state
+                            .decrypt(value, entry_key, org_id)?
+                            .split(|c: char| c == ',' || c.is_whitespace())
+                            .filter(|pattern| !pattern.is_empty())
+                            .map(str::to_string),
+                    );
+                }
+                CERTIFICATE_FIELD => {
+                    match ssh_agent_lib::ssh_key::Certificate::from_openssh(
+                        &state.decrypt(value, entry_key, org_id)?,
+                    ) {
+                        Ok(certificate)
+                            if certificate.public_key()
+                                == public_key.key_data() =>
+                        {
+                            certificates.push(certificate);
+                        }
+                        Ok(_) => log::warn!(
+                            "certificate in entry {} is not for its key",
+                            entry.id
+                        ),
+                        Err(e) => log::warn!(
+                            "failed to parse certificate in entry {}: {e}",
+                            entry.id
+                        ),
+                    }
+                }
+                // a destination which can't be parsed fails the whole key,
+                // since ignoring it would leave the key unrestricted
+                DESTINATION_FIELD => {
+                    destinations.push(parse_destination(
+                        &state.decrypt(value, entry_key, org_id)?,
+                    )?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Some(Self {
+            entry_id: entry.id.clone(),
+            name,
+            folder,
+            hosts,
+            public_key,
+            certificates,
+            private_key: private_key.clone(),
+            entry_key: entry.key.clone(),
+            org_id: entry.org_id.clone(),
+            confirm,
+            destinations,
+        }))
+    }
+
+    pub fn identities(&self) -> impl Iterator<Item = Identity> + '_ {
+        std::iter::once(None)
+            .chain(self.certificates.iter().map(Some))
+            .map(|certificate| Identity {
+                public_key: self.public_key.key_data().clone(),
+                certificate: certificate.cloned(),
+                comment: self.name.clone(),
+            })
+    }
+
+    fn in_folder(&self, folder: Option<&str>) -> bool {
+        folder.is_none_or(|folder| self.folder.as_deref() == Some(folder))
+    }
+
+    fn permits_host(&self, host: Option<&str>) -> bool {
+        self.hosts.is_empty()
+            || host
+                .is_some_and(|host| matches_host_patterns(&self.hosts, host))
+    }
+}
+
+impl AddedKey {
+    pub fn new(
+        credential: ssh_agent_lib::proto::Credential,
+        constraints: &[ssh_agent_lib::proto::KeyConstraint],
+    ) -> anyhow::Result<Self> {
+        let (private_key, certificate) = match credential {
+            ssh_agent_lib::proto::Credential::Key { privkey, comment } => (
+                ssh_agent_lib::ssh_key::PrivateKey::new(privkey, comment)?,
+                None,
+            ),
+            ssh_agent_lib::proto::Credential::Cert {
+                certificate,
+                privkey,
+                comment,
+                ..
+            } => (
+                ssh_agent_lib::ssh_key::PrivateKey::new(
+                    certificate_keypair(certificate.public_key(), privkey)?,
+                    comment,
+                )?,
+                Some(certificate),
+            ),
+        };
+
+        let mut confirm = false;
+        let mut expires = None;
+        let mut destinations = vec![];
+        for constraint in constraints {
+            match constraint {
+                ssh_agent_lib::proto::KeyConstraint::Lifetime(seconds) => {
+                    expires = Some(
+                        std::time::Instant::now()
+                            + std::time::Duration::from_secs(
+                                (*seconds).into(),
+                            ),
+                    );
+                }
+                ssh_agent_lib::proto::KeyConstraint::Confirm => {
+                    confirm = true;
+                }
+                ssh_agent_lib::proto::KeyConstraint::Extension(extension) => {
+                    // silently ignoring a constraint would make the key
+                    // more usable than the user asked for
+                    let restrict = extension.parse_key_constraint::<
+                        ssh_agent_lib::proto::extension::RestrictDestination,
+                    >()?;
+                    let Some(restrict) = restrict else {
+                        return Err(anyhow::anyhow!(
+                            "unsupported key constraint {}",
+                            extension.name
+                        ));
+                    };
+                    for constraint in restrict.constraints {
+                        validate_destination_constraint(&constraint)?;
+                        destinations.push(constraint);
+                    }
+                }
+            }
+        }
+
+        Ok(Self {
+            private_key,
+            certificate,
+            confirm,
+            expires,
+            destinations,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= std::time::Instant::now())
+    }
+
+    pub fn identity(&self) -> Identity {
+        Identity {
+            public_key: self.private_key.public_key().key_data().clone(),
+            certificate: self.certificate.clone(),
+            comment: self.private_key.comment().to_string(),
+        }
+    }
+
+    pub fn matches(
+        &self,
+        public_key: &ssh_agent_lib::ssh_key::public::KeyData,
+        certificate: Option<&ssh_agent_lib::ssh_key::Certificate>,
+    ) -> bool {
+        self.private_key.public_key().key_data() == public_key
+            && self.certificate.as_ref() == certificate
+    }
+}
+
+impl Identity {
+    // the wire encoding of the identity, as used in the agent protocol
+    pub fn blob(&self) -> anyhow::Result<Vec<u8>> {
+        if let Some(certificate) = &self.certificate {
+            Ok(certificate.to_bytes()?)
+        } else {
+            let mut blob = vec![];
+            ssh_agent_lib::ssh_encoding::Encode::encode(
+                &self.public_key,
+                &mut blob,
+            )?;
+            Ok(blob)
+        }
+    }
+}
+
+// the same checks that openssh's ssh-agent does when adding keys
+fn validate_destination_constraint(
+    constraint: &ssh_agent_lib::proto::extension::DestinationConstraint,
+) -> anyhow::Result<()> {
+    if !constraint.from.username.is_empty() {
+        return Err(anyhow::anyhow!(
+            "destination constraints can't restrict the source username"
+        ));
+    }
+    if constraint.from.hostname.is_empty() != constraint.from.keys.is_empty()
+    {
+        return Err(anyhow::anyhow!(
+            "destination constraint source must have both a hostname and \
+            host keys, or neither"
+        ));
+    }
+    if constraint.to.hostname.is_empty() || constraint.to.keys.is_empty() {
+        return Err(anyhow::anyhow!(
+            "destination constraint target must have a hostname and host \
+            keys"
+        ));
+    }
+    Ok(())
+}
+
+// parses a destination constraint of the form [FROM>]TO, where each hop is
+// given as a hostname (optionally prefixed with a username for the final
+// hop) followed by that host's public key, as in a known_hosts file. for
+// example:
+//
+//   jump ssh-ed25519 AAAA... > user@host ssh-ed25519 AAAA...
+//
+// the agent doesn't have access to known_hosts, so unlike ssh-add -h, the
+// host keys need to be given explicitly.
+pub fn parse_destination(
+    s: &str,
+) -> anyhow::Result<ssh_agent_lib::proto::extension::DestinationConstraint> {
+    let (from, to) = match s.split_once('>') {
+        Some((from, to)) => (parse_hop(from)?, parse_hop(to)?),
+        None => (
+            ssh_agent_lib::proto::extension::HostTuple {
+                username: String::new(),
+                hostname: String::new(),
+                keys: vec![],
+            },
+            parse_hop(s)?,
+        ),
+    };
+    let constraint =
+        ssh_agent_lib::proto::extension::DestinationConstraint { from, to };
+    validate_destination_constraint(&constraint)?;
+    Ok(constraint)
+}
+
+fn parse_hop(
+    s: &str,
+) -> anyhow::Result<ssh_agent_lib::proto::extension::HostTuple> {
+    let mut parts = s.split_whitespace();
+    let (Some(host), Some(algorithm), Some(key), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(anyhow::anyhow!(
+            "invalid destination hop '{}', expected [user@]host followed \
+            by a public key",
+            s.trim()
+        ));
+    };
+    let (username, hostname) = host.rsplit_once('@').unwrap_or(("", host));
+    let public_key = ssh_agent_lib::ssh_key::PublicKey::from_openssh(
+        &format!("{algorithm} {key}"),
+    )
+    .map_err(|e| {
+        anyhow::anyhow!("invalid host key for destination {hostname}: {e}")
+    })?;
+    Ok(ssh_agent_lib::proto::extension::HostTuple {
+        username: username.to_string(),
+        hostname: hostname.to_string(),
+        keys: vec![ssh_agent_lib::proto::extension::KeySpec {
+            keyblob: public_key.key_data().clone(),
+            is_ca: false,
+        }],
+    })
+}
+
+// keys added along with a certificate only include the private half of the
+// key, so we need to combine it with the public key from the certificate
+fn certificate_keypair(
+    public_key: &ssh_agent_lib::ssh_key::public::KeyData,
+    private_key: ssh_agent_lib::proto::PrivateKeyData,
+) -> anyhow::Result<ssh_agent_lib::ssh_key::private::KeypairData> {
+    use ssh_agent_lib::proto::{EcdsaPrivateKey, PrivateKeyData};
+    use ssh_agent_lib::ssh_key::{
+        private::{EcdsaKeypair, KeypairData, RsaKeypair},
+        public::{EcdsaPublicKey, KeyData},
+    };
+
+    Ok(match (public_key, private_key) {
+        (KeyData::Ed25519(_), PrivateKeyData::Ed25519(keypair)) => {
+            KeypairData::Ed25519(keypair)
+        }
+        (
+            KeyData::Ecdsa(EcdsaPublicKey::NistP256(public)),
+            PrivateKeyData::Ecdsa(EcdsaPrivateKey::NistP256(private)),
+        ) => KeypairData::Ecdsa(EcdsaKeypair::NistP256 {
+            public: *public,
+            private,
+        }),
+        (
+            KeyData::Ecdsa(EcdsaPublicKey::NistP384(public)),
+            PrivateKeyData::Ecdsa(EcdsaPrivateKey::NistP384(private)),
+        ) => KeypairData::Ecdsa(EcdsaKeypair::NistP384 {
+            public: *public,
+            private,
+        }),
+        (
+            KeyData::Ecdsa(EcdsaPublicKey::NistP521(public)),
+            PrivateKeyData::Ecdsa(EcdsaPrivateKey::NistP521(private)),
+        ) => KeypairData::Ecdsa(EcdsaKeypair::NistP521 {
+            public: *public,
+            private,
+        }),
+        (KeyData::Rsa(public), PrivateKeyData::Rsa(private)) => {
+            KeypairData::Rsa(RsaKeypair {
+                public: public.clone(),
+                private,
+            })
+        }
+        _ => {
+            return Err(anyhow::anyhow!(
+                "unsupported certificate key type {}",
+                public_key.algorithm()
+            ))
+        }
+    })
+}
+
+// matches a host against a list of patterns in the same way as the Host
+// directive in ssh_config: the host matches if it matches any of the
+// patterns, unless it also matches a pattern negated with !
+fn matches_host_patterns(patterns: &[String], host: &str) -> bool {
+    let host = host.to_lowercase();
+    let mut matched = false;
+    for pattern in patterns {
+        let pattern = pattern.to_lowercase();
+        if let Some(pattern) = pattern.strip_prefix('!') {
+            if glob_match(pattern.as_bytes(), host.as_bytes()) {
+                return false;
+            }
+        } else if glob_match(pattern.as_bytes(), host.as_bytes()) {
+            matched = true;
+        }
+    }
+    matched
+}
+
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => s.is_empty(),
+        Some((b'*', rest)) => {
+            (0..=s.len()).any(|i| glob_match(rest, &s[i..]))
+        }
+        Some((b'?', rest)) => !s.is_empty() && glob_match(rest, &s[1..]),
+        Some((c, rest)) => s.first() == Some(c) && glob_match(rest, &s[1..]),
+    }
+}
+
+fn fingerprint(public_key: &ssh_agent_lib::ssh_key::PublicKey) -> String {
+    public_key
+        .fingerprint(ssh_agent_lib::ssh_key::HashAlg::Sha256)
+        .to_string()
+}
+
+fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_lowercase().as_str(),
+        "true" | "yes" | "on" | "1"
+    )
+}
+
+#[test]
+fn test_matches_host_patterns() {
+    let patterns = vec![
+        "*.example.com".to_string(),
+        "github.com".to_string(),
+        "!bastion.example.com".to_string(),
+    ];
+    assert!(matches_host_patterns(&patterns, "github.com"));
+    assert!(matches_host_patterns(&patterns, "GitHub.com"));
+    assert!(matches_host_patterns(&patterns, "www.example.com"));
+    assert!(!matches_host_patterns(&patterns, "bastion.example.com"));
+    assert!(!matches_host_patterns(&patterns, "example.com"));
+    assert!(!matches_host_patterns(&patterns, "gitlab.com"));
+    assert!(matches_host_patterns(&["host?".to_string()], "host1"));
+    assert!(!matches_host_patterns(&["host?".to_string()], "host10"));
+}
+
+#[test]
+fn test_find_restricted() {
+    let public_key = ssh_agent_lib::ssh_key::PrivateKey::random(
+        &mut rand_8::rngs::OsRng,
+        ssh_agent_lib::ssh_key::Algorithm::Ed25519,
+    )
+    .unwrap()
+    .public_key()
+    .clone();
+    let index = Index {
+        keys: vec![Key {
+            entry_id: "id".to_string(),
+            name: "github".to_string(),
+            folder: None,
+            hosts: vec!["github.com".to_string()],
+            public_key: public_key.clone(),
+            certificates: vec![],
+            private_key: None,
+            entry_key: None,
+            org_id: None,
+            confirm: false,
+            destinations: vec![],
+        }],
+        fingerprints: std::iter::once((fingerprint(&public_key), 0))
+            .collect(),
+    };
+    assert!(index.find(&public_key, None, Some("github.com")).is_some());
+    assert!(index.find(&public_key, None, Some("gitlab.com")).is_none());
+    assert!(index.find(&public_key, None, None).is_none());
+}
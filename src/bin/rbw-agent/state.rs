@@ -1,3 +1,4 @@
+use anyhow::Context as _;
 use sha2::Digest as _;
 
 pub struct State {
@@ -6,11 +7,38 @@ pub struct State {
         Option<std::collections::HashMap<String, rbw::locked::Keys>>,
     pub timeout: crate::timeout::Timeout,
     pub timeout_duration: std::time::Duration,
+    pub timeout_exempt_actions: std::collections::HashSet<String>,
+    pub lifetime: crate::timeout::Timeout,
+    pub lifetime_duration: std::time::Duration,
+    pub lifetime_deadline: Option<std::time::SystemTime>,
+    pub session_keyring: bool,
+    pub audit_log: bool,
+    // read once at startup rather than on each request, since anything
+    // running as the user can change the config file, including the
+    // clients that this is meant to restrict
+    pub client_policy: rbw::config::ClientPolicy,
+    pub allowed_clients: std::collections::HashSet<std::path::PathBuf>,
+    pub ssh_confirm: bool,
+    pub ssh_refuse_rsa_sha1: bool,
+    pub ssh_refuse_forwarded: bool,
     pub sync_timeout: crate::timeout::Timeout,
     pub sync_timeout_duration: std::time::Duration,
+    pub sync_lock: std::sync::Arc<tokio::sync::Mutex<()>>,
     pub notifications_handler: crate::notifications::Handler,
     pub master_password_reprompt: std::collections::HashSet<[u8; 32]>,
     pub master_password_reprompt_initialized: bool,
+    pub entry_ids: std::collections::HashMap<[u8; 32], String>,
+    pub ssh_key_index: Option<crate::ssh_keys::Index>,
+    pub search_index: Option<crate::search_index::Index>,
+    pub ssh_added_keys: Vec<crate::ssh_keys::AddedKey>,
+    pub approved_clients: std::collections::HashSet<std::path::PathBuf>,
+
+    // the environment of the request which last successfully unlocked the
+    // agent. client confirmation prompts are displayed here rather than in
+    // the environment sent by the (as yet untrusted) client, since
+    // otherwise the client could just point the prompt at a display that
+    // it controls and confirm it itself.
+    pub unlock_environment: Option<rbw::protocol::Environment>,
 
     // this is stored here specifically for the use of the ssh agent, because
     // requests made to the ssh agent don't include an environment, and so we
@@ -39,14 +67,163 @@ impl State {
         self.priv_key.is_none() || self.org_keys.is_none()
     }
 
+    // decrypts a cipherstring using the in-memory keys. this bypasses
+    // master password reprompt, and so should only be used for data which
+    // isn't protected by it.
+    pub fn decrypt(
+        &self,
+        cipherstring: &str,
+        entry_key: Option<&str>,
+        org_id: Option<&str>,
+    ) -> anyhow::Result<String> {
+        let Some(keys) = self.key(org_id) else {
+            return Err(anyhow::anyhow!(
+                "failed to find decryption keys in in-memory state"
+            ));
+        };
+        let entry_key = if let Some(entry_key) = entry_key {
+            let key_cipherstring = rbw::cipherstring::CipherString::new(
+                entry_key,
+            )
+            .context("failed to parse individual item encryption key")?;
+            Some(rbw::locked::Keys::new(
+                key_cipherstring.decrypt_locked_symmetric(keys).context(
+                    "failed to decrypt individual item encryption key",
+                )?,
+            ))
+        } else {
+            None
+        };
+
+        let cipherstring = rbw::cipherstring::CipherString::new(cipherstring)
+            .context("failed to parse encrypted secret")?;
+        String::from_utf8(
+            cipherstring
+                .decrypt_symmetric(keys, entry_key.as_ref())
+                .context("failed to decrypt encrypted secret")?,
+        )
+        .context("failed to parse decrypted secret")
+    }
+
+    // rebuilds the ssh key index from the given entries, or discards it if
+    // we aren't currently unlocked (in which case it will be rebuilt on
+    // unlock)
+    pub fn set_ssh_key_index(&mut self, entries: &[rbw::db::Entry]) {
+        self.ssh_key_index = if self.needs_unlock() {
+            None
+        } else {
+            Some(crate::ssh_keys::Index::build(self, entries))
+        };
+    }
+
+    // likewise for the search index
+    pub fn set_search_index(
+        &mut self,
+        entries: &[rbw::db::Entry],
+        modified: Option<std::time::SystemTime>,
+    ) {
+        self.search_index = if self.needs_unlock() {
+            None
+        } else {
+            Some(crate::search_index::Index::build(self, entries, modified))
+        };
+    }
+
+    // keys added via ssh-add, with any whose lifetime has expired removed
+    pub fn ssh_added_keys(&mut self) -> &mut Vec<crate::ssh_keys::AddedKey> {
+        self.ssh_added_keys.retain(|key| !key.is_expired());
+        &mut self.ssh_added_keys
+    }
+
     pub fn set_timeout(&self) {
         self.timeout.set(self.timeout_duration);
+        if self.session_keyring && !self.needs_unlock() {
+            if let Err(e) = rbw::keyring::set_timeout(self.keyring_timeout())
+            {
+                log::warn!("failed to update session keyring timeout: {e:#}");
+            }
+        }
+    }
+
+    // the idle timeout is reset by every request that uses the unlocked
+    // keys, except for the ones that the user has explicitly configured to
+    // not count as activity (for instance, status bar scripts polling the
+    // agent)
+    pub fn set_timeout_for(&self, action: &str) {
+        if !self.timeout_exempt_actions.contains(action) {
+            self.set_timeout();
+        }
+    }
+
+    // unlike the idle timeout, the lifetime timer is only started when the
+    // keys are first unlocked, and is never reset by later activity
+    pub fn set_lifetime(&mut self) {
+        if self.lifetime_duration > std::time::Duration::ZERO {
+            self.lifetime.set(self.lifetime_duration);
+            self.lifetime_deadline =
+                Some(std::time::SystemTime::now() + self.lifetime_duration);
+        }
+    }
+
+    // continues the lifetime of keys unlocked by a previous agent, rather
+    // than starting it over
+    pub fn resume_lifetime(&mut self, deadline: std::time::SystemTime) {
+        let remaining = deadline
+            .duration_since(std::time::SystemTime::now())
+            .unwrap_or_default();
+        self.lifetime.set(remaining);
+        self.lifetime_deadline = Some(deadline);
+    }
+
+    // stores the unlocked keys in the session keyring (if enabled), so that
+    // they survive agent restarts
+    pub fn store_keys(&self) {
+        if !self.session_keyring {
+            return;
+        }
+        let Some(keys) = &self.priv_key else {
+            return;
+        };
+        if let Err(e) = rbw::keyring::store(
+            keys,
+            self.lifetime_deadline,
+            self.keyring_timeout(),
+        ) {
+            log::warn!("failed to store keys in session keyring: {e:#}");
+        }
+    }
+
+    // keys in the keyring expire along with the idle timeout, or the
+    // lifetime if that ends sooner
+    fn keyring_timeout(&self) -> std::time::Duration {
+        let remaining_lifetime = self.lifetime_deadline.map(|deadline| {
+            deadline
+                .duration_since(std::time::SystemTime::now())
+                .unwrap_or_default()
+        });
+        remaining_lifetime.map_or(self.timeout_duration, |remaining| {
+            remaining.min(self.timeout_duration)
+        })
     }
 
     pub fn clear(&mut self) {
         self.priv_key = None;
         self.org_keys = None;
         self.timeout.clear();
+        self.lifetime.clear();
+        self.lifetime_deadline = None;
+        if self.session_keyring {
+            if let Err(e) = rbw::keyring::remove() {
+                log::warn!(
+                    "failed to remove keys from session keyring: {e:#}"
+                );
+            }
+        }
+        self.approved_clients.clear();
+        self.unlock_environment = None;
+        self.ssh_key_index = None;
+        self.search_index = None;
+        self.ssh_added_keys.clear();
     }
 
     pub fn set_sync_timeout(&self) {
@@ -129,6 +306,30 @@ impl State {
         self.master_password_reprompt_initialized = true;
     }
 
+    // similarly, the audit log wants to record which entry a decrypted
+    // cipherstring belongs to, so we keep a map of cipherstring hashes back
+    // to entry ids. this is updated at the same points as the master
+    // password reprompt set.
+    pub fn set_entry_ids(&mut self, entries: &[rbw::db::Entry]) {
+        self.entry_ids.clear();
+
+        let mut hasher = sha2::Sha256::new();
+        for entry in entries {
+            for cipherstring in entry.cipherstrings() {
+                hasher.update(cipherstring);
+                self.entry_ids
+                    .insert(hasher.finalize_reset().into(), entry.id.clone());
+            }
+        }
+    }
+
+    pub fn entry_id(&self, cipherstring: &str) -> Option<String> {
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(cipherstring);
+        let hash: [u8; 32] = hasher.finalize().into();
+        self.entry_ids.get(&hash).cloned()
+    }
+
     pub fn master_password_reprompt_initialized(&self) -> bool {
         self.master_password_reprompt_initialized
     }
@@ -14,6 +14,10 @@ pub fn unlock() -> anyhow::Result<()> {
     simple_action(rbw::protocol::Action::Unlock)
 }
 
+pub fn set_pin() -> anyhow::Result<()> {
+    simple_action(rbw::protocol::Action::SetPin)
+}
+
 pub fn unlocked() -> anyhow::Result<()> {
     match crate::sock::Sock::connect() {
         Ok(mut sock) => {
@@ -105,6 +109,23 @@ pub fn decrypt(
     }
 }
 
+pub fn index() -> anyhow::Result<Vec<rbw::protocol::IndexEntry>> {
+    let mut sock = connect()?;
+    sock.send(&rbw::protocol::Request::new(
+        get_environment(),
+        rbw::protocol::Action::Index,
+    ))?;
+
+    let res = sock.recv()?;
+    match res {
+        rbw::protocol::Response::Index { entries } => Ok(entries),
+        rbw::protocol::Response::Error { error } => {
+            Err(anyhow::anyhow!("failed to load search index: {error}"))
+        }
+        _ => Err(anyhow::anyhow!("unexpected message: {res:?}")),
+    }
+}
+
 pub fn encrypt(
     plaintext: &str,
     org_id: Option<&str>,
@@ -1,11 +1,15 @@
 use std::{fmt::Write as _, io::Write as _, os::unix::ffi::OsStrExt as _};
 
 use anyhow::Context as _;
+use is_terminal::IsTerminal as _;
 
 // The default number of seconds the generated TOTP
 // code lasts for before a new one must be generated
 const TOTP_DEFAULT_STEP: u64 = 30;
 
+const DB_LOCK_TIMEOUT: std::time::Duration =
+    std::time::Duration::from_secs(30);
+
 const MISSING_CONFIG_HELP: &str =
     "Before using rbw, you must configure the email address you would like to \
     use to log in to the server by running:\n\n    \
@@ -187,6 +191,8 @@ struct DecryptedListCipher {
     uris: Option<Vec<String>>,
     #[serde(rename = "type")]
     entry_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pending: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -200,6 +206,7 @@ struct DecryptedSearchCipher {
     user: Option<String>,
     uris: Vec<(String, Option<rbw::api::UriMatchType>)>,
     fields: Vec<String>,
+    field_names: Vec<String>,
     notes: Option<String>,
 }
 
@@ -295,30 +302,45 @@ impl DecryptedSearchCipher {
         true
     }
 
-    fn search_match(&self, term: &str, folder: Option<&str>) -> bool {
-        if let Some(folder) = folder {
-            if self.folder.as_deref() != Some(folder) {
-                return false;
-            }
-        }
-
-        let mut fields = vec![self.name.clone()];
-        if let Some(notes) = &self.notes {
-            fields.push(notes.clone());
-        }
-        if let Some(user) = &self.user {
-            fields.push(user.clone());
+    fn searchable(&self) -> crate::search::Searchable<'_> {
+        crate::search::Searchable {
+            name: &self.name,
+            user: self.user.as_deref(),
+            folder: self.folder.as_deref(),
+            entry_type: &self.entry_type,
+            uris: self.uris.iter().map(|(uri, _)| uri.as_str()).collect(),
+            field_names: self
+                .field_names
+                .iter()
+                .map(String::as_str)
+                .collect(),
+            field_values: self.fields.iter().map(String::as_str).collect(),
+            notes: self.notes.as_deref(),
         }
-        fields.extend(self.uris.iter().map(|(uri, _)| uri).cloned());
-        fields.extend(self.fields.iter().cloned());
+    }
+}
 
-        for field in fields {
-            if field.to_lowercase().contains(&term.to_lowercase()) {
-                return true;
-            }
+impl From<rbw::protocol::IndexEntry> for DecryptedSearchCipher {
+    fn from(value: rbw::protocol::IndexEntry) -> Self {
+        Self {
+            id: value.id,
+            entry_type: value.entry_type,
+            folder: value.folder,
+            name: value.name,
+            user: value.user,
+            uris: value.uris,
+            fields: value
+                .fields
+                .iter()
+                .filter_map(|field| field.value.clone())
+                .collect(),
+            field_names: value
+                .fields
+                .into_iter()
+                .filter_map(|field| field.name)
+                .collect(),
+            notes: value.notes,
         }
-
-        false
     }
 }
 
@@ -331,6 +353,7 @@ impl From<DecryptedSearchCipher> for DecryptedListCipher {
             user: value.user,
             folder: value.folder,
             uris: Some(value.uris.into_iter().map(|(s, _)| s).collect()),
+            pending: None,
         }
     }
 }
@@ -853,6 +876,64 @@ impl DecryptedCipher {
         }
     }
 
+    // the values in the entry as (label, value, secret) tuples, for showing
+    // the differences between two versions of an entry
+    fn diff_fields(&self) -> Vec<(String, String, bool)> {
+        let mut fields = vec![("name".to_string(), self.name.clone(), false)];
+        fields.extend(
+            self.folder
+                .clone()
+                .map(|folder| ("folder".to_string(), folder, false)),
+        );
+        if let Ok(serde_json::Value::Object(data)) =
+            serde_json::to_value(&self.data)
+        {
+            for (key, value) in data {
+                // everything that can be protected by a master password
+                // reprompt, plus license numbers
+                let secret = matches!(
+                    key.as_str(),
+                    "password"
+                        | "totp"
+                        | "number"
+                        | "code"
+                        | "ssn"
+                        | "passport_number"
+                        | "license_number"
+                        | "private_key"
+                );
+                match value {
+                    serde_json::Value::String(value) => {
+                        fields.push((key, value, secret));
+                    }
+                    serde_json::Value::Array(uris) => {
+                        fields.extend(uris.iter().filter_map(|uri| {
+                            uri.get("uri")
+                                .and_then(serde_json::Value::as_str)
+                                .map(|uri| {
+                                    (key.clone(), uri.to_string(), false)
+                                })
+                        }));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for field in &self.fields {
+            fields.push((
+                format!("field {}", field.name.as_deref().unwrap_or("")),
+                field.value.clone().unwrap_or_default(),
+                field.ty == Some(rbw::api::FieldType::Hidden),
+            ));
+        }
+        fields.extend(
+            self.notes
+                .clone()
+                .map(|notes| ("notes".to_string(), notes, false)),
+        );
+        fields
+    }
+
     /// This implementation mirror the `fn display_fied` method on which field to list
     fn display_fields_list(&self) {
         match &self.data {
@@ -1063,8 +1144,7 @@ enum DecryptedData {
     },
 }
 
-#[derive(Debug, Clone, serde::Serialize)]
-#[cfg_attr(test, derive(Eq, PartialEq))]
+#[derive(Debug, Clone, serde::Serialize, Eq, PartialEq)]
 struct DecryptedField {
     name: Option<String>,
     value: Option<String>,
@@ -1186,6 +1266,7 @@ enum ListField {
     Folder,
     Uri,
     EntryType,
+    Pending,
 }
 
 impl ListField {
@@ -1197,6 +1278,7 @@ impl ListField {
             Self::Folder,
             Self::Uri,
             Self::EntryType,
+            Self::Pending,
         ]
     }
 }
@@ -1211,6 +1293,7 @@ impl std::convert::TryFrom<&String> for ListField {
             "user" => Self::User,
             "folder" => Self::Folder,
             "type" => Self::EntryType,
+            "pending" => Self::Pending,
             _ => return Err(anyhow::anyhow!("unknown field {s}")),
         })
     }
@@ -1252,6 +1335,19 @@ pub fn config_set(key: &str, value: &str) -> anyhow::Result<()> {
             config.client_cert_path =
                 Some(std::path::PathBuf::from(value.to_string()));
         }
+        "client_policy" => {
+            config.client_policy = value
+                .parse()
+                .context("failed to parse value for client_policy")?;
+        }
+        "allowed_clients" => {
+            config.allowed_clients = value
+                .split(',')
+                .map(str::trim)
+                .filter(|client| !client.is_empty())
+                .map(std::path::PathBuf::from)
+                .collect();
+        }
         "lock_timeout" => {
             let timeout = value
                 .parse()
@@ -1262,13 +1358,77 @@ pub fn config_set(key: &str, value: &str) -> anyhow::Result<()> {
                 config.lock_timeout = timeout;
             }
         }
+        "unlock_lifetime" => {
+            config.unlock_lifetime = value
+                .parse()
+                .context("failed to parse value for unlock_lifetime")?;
+        }
+        "lock_timeout_exempt_actions" => {
+            let actions: Vec<_> = value
+                .split(',')
+                .map(str::trim)
+                .filter(|action| !action.is_empty())
+                .map(str::to_string)
+                .collect();
+            for action in &actions {
+                if !rbw::config::LOCK_TIMEOUT_ACTIONS
+                    .contains(&action.as_str())
+                {
+                    return Err(anyhow::anyhow!(
+                        "invalid action for lock_timeout_exempt_actions: \
+                        {action}"
+                    ));
+                }
+            }
+            config.lock_timeout_exempt_actions = actions;
+        }
         "sync_interval" => {
             let interval = value
                 .parse()
                 .context("failed to parse value for sync_interval")?;
             config.sync_interval = interval;
         }
+        "db_backups" => {
+            config.db_backups = value
+                .parse()
+                .context("failed to parse value for db_backups")?;
+        }
         "pinentry" => config.pinentry = value.to_string(),
+        "prompt" => {
+            config.prompt =
+                value.parse().context("failed to parse value for prompt")?;
+        }
+        "askpass" => config.askpass = Some(value.to_string()),
+        "audit_log" => config.audit_log = parse_bool(key, value)?,
+        "ssh_confirm" => config.ssh_confirm = parse_bool(key, value)?,
+        "ssh_refuse_rsa_sha1" => {
+            config.ssh_refuse_rsa_sha1 = parse_bool(key, value)?;
+        }
+        "ssh_refuse_forwarded" => {
+            config.ssh_refuse_forwarded = parse_bool(key, value)?;
+        }
+        "ssh_folder" => config.ssh_folder = Some(value.to_string()),
+        "session_keyring" => {
+            config.session_keyring = parse_bool(key, value)?;
+        }
+        "ssh_max_identities" => {
+            config.ssh_max_identities =
+                Some(value.parse().context(
+                    "failed to parse value for ssh_max_identities",
+                )?);
+        }
+        "lock_on_suspend" => {
+            config.lock_on_suspend = parse_bool(key, value)?;
+        }
+        "lock_on_session_lock" => {
+            config.lock_on_session_lock = parse_bool(key, value)?;
+        }
+        "lock_on_screensaver" => {
+            config.lock_on_screensaver = parse_bool(key, value)?;
+        }
+        "lock_on_logout" => {
+            config.lock_on_logout = parse_bool(key, value)?;
+        }
         _ => return Err(anyhow::anyhow!("invalid config key: {key}")),
     }
     config.save()?;
@@ -1294,10 +1454,34 @@ pub fn config_unset(key: &str) -> anyhow::Result<()> {
         "ui_url" => config.ui_url = None,
         "notifications_url" => config.notifications_url = None,
         "client_cert_path" => config.client_cert_path = None,
+        "client_policy" => {
+            config.client_policy = rbw::config::ClientPolicy::default();
+        }
+        "allowed_clients" => config.allowed_clients = vec![],
         "lock_timeout" => {
             config.lock_timeout = rbw::config::default_lock_timeout();
         }
+        "unlock_lifetime" => config.unlock_lifetime = 0,
+        "lock_timeout_exempt_actions" => {
+            config.lock_timeout_exempt_actions = vec![];
+        }
+        "db_backups" => {
+            config.db_backups = rbw::config::default_db_backups();
+        }
         "pinentry" => config.pinentry = rbw::config::default_pinentry(),
+        "prompt" => config.prompt = rbw::config::PromptBackend::default(),
+        "askpass" => config.askpass = None,
+        "audit_log" => config.audit_log = false,
+        "ssh_confirm" => config.ssh_confirm = false,
+        "ssh_refuse_rsa_sha1" => config.ssh_refuse_rsa_sha1 = false,
+        "ssh_refuse_forwarded" => config.ssh_refuse_forwarded = false,
+        "ssh_folder" => config.ssh_folder = None,
+        "session_keyring" => config.session_keyring = false,
+        "ssh_max_identities" => config.ssh_max_identities = None,
+        "lock_on_suspend" => config.lock_on_suspend = false,
+        "lock_on_session_lock" => config.lock_on_session_lock = false,
+        "lock_on_screensaver" => config.lock_on_screensaver = false,
+        "lock_on_logout" => config.lock_on_logout = false,
         _ => return Err(anyhow::anyhow!("invalid config key: {key}")),
     }
     config.save()?;
@@ -1312,6 +1496,12 @@ pub fn config_unset(key: &str) -> anyhow::Result<()> {
     Ok(())
 }
 
+fn parse_bool(key: &str, value: &str) -> anyhow::Result<bool> {
+    value
+        .parse()
+        .with_context(|| format!("failed to parse value for {key}"))
+}
+
 fn clipboard_store(val: &str) -> anyhow::Result<()> {
     ensure_agent()?;
     crate::actions::clipboard_store(val)?;
@@ -1341,6 +1531,26 @@ pub fn unlock() -> anyhow::Result<()> {
     Ok(())
 }
 
+pub fn pin_set() -> anyhow::Result<()> {
+    ensure_agent()?;
+    crate::actions::login()?;
+    crate::actions::set_pin()?;
+
+    Ok(())
+}
+
+pub fn pin_clear() -> anyhow::Result<()> {
+    let config = rbw::config::Config::load()?;
+    let Some(email) = &config.email else {
+        return Err(anyhow::anyhow!(
+            "failed to find email address in config"
+        ));
+    };
+    rbw::pin::Pin::remove(&config.server_name(), email)?;
+
+    Ok(())
+}
+
 pub fn unlocked() -> anyhow::Result<()> {
     // not ensure_agent, because we don't want `rbw unlocked` to start the
     // agent if it's not running
@@ -1355,6 +1565,70 @@ pub fn sync() -> anyhow::Result<()> {
     crate::actions::login()?;
     crate::actions::sync()?;
 
+    let conflicts = load_db()?
+        .pending
+        .iter()
+        .filter(|change| change.conflict)
+        .count();
+    if conflicts > 0 {
+        eprintln!(
+            "warning: {conflicts} queued change(s) couldn't be sent because \
+            the entries were also changed on the server (see `rbw pending \
+            list`)"
+        );
+    }
+
+    Ok(())
+}
+
+pub fn pending_list() -> anyhow::Result<()> {
+    unlock()?;
+
+    let db = load_db()?;
+    for change in &db.pending {
+        let entry = change.action.entry();
+        let name = crate::actions::decrypt(
+            &entry.name,
+            entry.key.as_deref(),
+            entry.org_id.as_deref(),
+        )?;
+        // unwrap is safe because every entry in the queue has a status
+        let status = pending_status(&db, &entry.id).unwrap();
+        println!("{status}\t{name}");
+    }
+
+    Ok(())
+}
+
+pub fn pending_discard(needle: Option<&str>) -> anyhow::Result<()> {
+    unlock()?;
+
+    let lock = lock_db()?;
+    let mut db = load_db()?;
+    let mut discard = vec![];
+    for change in &db.pending {
+        let entry = change.action.entry();
+        let name = crate::actions::decrypt(
+            &entry.name,
+            entry.key.as_deref(),
+            entry.org_id.as_deref(),
+        )?;
+        if needle.is_none_or(|needle| needle == name || needle == entry.id) {
+            println!("discarding {} of {name}", change.action.name());
+            discard.push(entry.id.clone());
+        }
+    }
+    if let Some(needle) = needle {
+        if discard.is_empty() {
+            return Err(anyhow::anyhow!(
+                "couldn't find queued changes for '{needle}'"
+            ));
+        }
+    }
+    db.pending
+        .retain(|change| !discard.iter().any(|id| id == change.action.id()));
+    save_db(&db, &lock)?;
+
     Ok(())
 }
 
@@ -1370,12 +1644,14 @@ pub fn list(fields: &[String], raw: bool) -> anyhow::Result<()> {
 
     unlock()?;
 
-    let db = load_db()?;
-    let mut entries: Vec<DecryptedListCipher> = db
-        .entries
-        .iter()
-        .map(|entry| decrypt_list_cipher(entry, &fields))
-        .collect::<anyhow::Result<_>>()?;
+    let db = load_local_db()?;
+    let mut entries: Vec<DecryptedListCipher> = search_ciphers(&db.entries)?
+        .into_iter()
+        .map(|(entry, decrypted)| DecryptedListCipher {
+            pending: pending_status(&db, &entry.id),
+            ..decrypted.into()
+        })
+        .collect();
     entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
 
     print_entry_list(&entries, &fields, raw)?;
@@ -1397,7 +1673,7 @@ pub fn get(
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let db = load_db()?;
+    let db = load_local_db()?;
 
     let desc = format!(
         "{}{}",
@@ -1405,9 +1681,15 @@ pub fn get(
         needle
     );
 
-    let (_, decrypted) =
+    let (entry, decrypted) =
         find_entry(&db, needle, user, folder, ignore_case)
             .with_context(|| format!("couldn't find entry for '{desc}'"))?;
+    if let Some(pending) = pending_status(&db, &entry.id) {
+        eprintln!(
+            "note: this entry has a pending {pending} which hasn't been \
+            sent to the server yet"
+        );
+    }
     if list_fields {
         decrypted.display_fields_list();
     } else if raw {
@@ -1423,6 +1705,18 @@ pub fn get(
     Ok(())
 }
 
+// describes the change to the entry which hasn't been sent to the server
+// yet, if any
+fn pending_status(db: &rbw::db::Db, id: &str) -> Option<String> {
+    db.pending(id).map(|change| {
+        if change.conflict {
+            format!("{} (conflict)", change.action.name())
+        } else {
+            change.action.name().to_string()
+        }
+    })
+}
+
 fn print_entry_list(
     entries: &[DecryptedListCipher],
     fields: &[ListField],
@@ -1464,6 +1758,10 @@ fn print_entry_list(
                             std::string::ToString::to_string,
                         )
                     }
+                    ListField::Pending => entry.pending.as_ref().map_or_else(
+                        String::new,
+                        std::string::ToString::to_string,
+                    ),
                 })
                 .collect();
 
@@ -1485,8 +1783,11 @@ pub fn search(
     term: &str,
     fields: &[String],
     folder: Option<&str>,
+    regex: bool,
+    deep: bool,
     raw: bool,
 ) -> anyhow::Result<()> {
+    let query = crate::search::Query::parse(term, regex, deep)?;
     let fields: Vec<ListField> = if raw {
         ListField::all()
     } else {
@@ -1498,37 +1799,121 @@ pub fn search(
 
     unlock()?;
 
-    let db = load_db()?;
+    let db = load_local_db()?;
 
-    let mut entries: Vec<DecryptedListCipher> = db
-        .entries
-        .iter()
-        .map(decrypt_search_cipher)
-        .filter(|entry| {
-            entry
-                .as_ref()
-                .map(|entry| entry.search_match(term, folder))
-                .unwrap_or(true)
+    let mut matches: Vec<_> = search_ciphers(&db.entries)?
+        .into_iter()
+        .filter(|(_, decrypted)| {
+            folder.is_none_or(|folder| {
+                decrypted.folder.as_deref() == Some(folder)
+            })
         })
-        .map(|entry| entry.map(std::convert::Into::into))
-        .collect::<Result<_, anyhow::Error>>()?;
-    entries.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        .filter_map(|(entry, decrypted)| {
+            query
+                .rank(&decrypted.searchable())
+                .map(|rank| (rank, entry, decrypted))
+        })
+        .collect();
+    matches.sort_unstable_by(|(a_rank, _, a), (b_rank, _, b)| {
+        a_rank.cmp(b_rank).then_with(|| a.name.cmp(&b.name))
+    });
+    let entries: Vec<DecryptedListCipher> = matches
+        .into_iter()
+        .map(|(_, entry, decrypted)| DecryptedListCipher {
+            pending: pending_status(&db, &entry.id),
+            ..decrypted.into()
+        })
+        .collect();
 
     print_entry_list(&entries, &fields, raw)?;
 
     Ok(())
 }
 
+pub fn pick(term: &str, clipboard: bool) -> anyhow::Result<()> {
+    unlock()?;
+
+    let db = load_local_db()?;
+    // every action is on a login's fields
+    let items = search_ciphers(&db.entries)?
+        .into_iter()
+        .filter(|(entry, _)| {
+            matches!(entry.data, rbw::db::EntryData::Login { .. })
+        })
+        .map(|(_, decrypted)| crate::pick::Item {
+            id: decrypted.id,
+            name: decrypted.name,
+            user: decrypted.user,
+            folder: decrypted.folder,
+            entry_type: decrypted.entry_type,
+            uris: decrypted.uris.into_iter().map(|(uri, _)| uri).collect(),
+        })
+        .collect();
+    let Some((id, action)) = crate::pick::run(items, term)? else {
+        return Ok(());
+    };
+
+    let entry = db
+        .entries
+        .iter()
+        .find(|entry| entry.id == id)
+        .ok_or_else(|| anyhow::anyhow!("couldn't find entry {id}"))?;
+    let decrypted = decrypt_cipher(entry)?;
+    let DecryptedData::Login {
+        username,
+        password,
+        totp,
+        uris,
+    } = decrypted.data
+    else {
+        return Err(anyhow::anyhow!("not a login entry"));
+    };
+    let value = match action {
+        crate::pick::Action::Password => password
+            .ok_or_else(|| anyhow::anyhow!("entry has no password"))?,
+        crate::pick::Action::Username => username
+            .ok_or_else(|| anyhow::anyhow!("entry has no username"))?,
+        crate::pick::Action::Totp => {
+            generate_totp(&totp.ok_or_else(|| {
+                anyhow::anyhow!("entry does not contain a totp secret")
+            })?)?
+        }
+        crate::pick::Action::OpenUri => {
+            let uri = uris
+                .and_then(|uris| uris.into_iter().next())
+                .ok_or_else(|| anyhow::anyhow!("entry has no uris"))?
+                .uri;
+            // uris are often stored without a scheme
+            let uri = if uri.contains("://") {
+                uri
+            } else {
+                format!("https://{uri}")
+            };
+            open::that_detached(&uri)
+                .with_context(|| format!("failed to open {uri}"))?;
+            return Ok(());
+        }
+    };
+    val_display_or_store(clipboard, &value);
+
+    Ok(())
+}
+
+#[allow(clippy::fn_params_excessive_bools)]
 pub fn code(
     needle: Needle,
     user: Option<&str>,
     folder: Option<&str>,
     clipboard: bool,
     ignore_case: bool,
+    field: Option<&str>,
+    verbose: bool,
+    next: bool,
+    wait: Option<u64>,
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let db = load_db()?;
+    let db = load_local_db()?;
 
     let desc = format!(
         "{}{}",
@@ -1536,25 +1921,241 @@ pub fn code(
         needle
     );
 
-    let (_, decrypted) =
+    let (entry, decrypted) =
         find_entry(&db, needle, user, folder, ignore_case)
             .with_context(|| format!("couldn't find entry for '{desc}'"))?;
 
-    if let DecryptedData::Login { totp, .. } = decrypted.data {
-        if let Some(totp) = totp {
-            val_display_or_store(clipboard, &generate_totp(&totp)?);
-        } else {
+    let (totp, field_index) = if let Some(field) = field {
+        let (i, totp) = find_field(&decrypted.fields, field)?;
+        (totp.to_string(), Some(i))
+    } else {
+        let DecryptedData::Login { totp, .. } = decrypted.data else {
+            return Err(anyhow::anyhow!(
+                "not a login entry (use --field to generate a code from a \
+                custom field)"
+            ));
+        };
+        let Some(totp) = totp else {
             return Err(anyhow::anyhow!(
                 "entry does not contain a totp secret"
             ));
+        };
+        (totp, None)
+    };
+    let totp_params = parse_totp_secret(&totp)?;
+
+    let code = if let Some(counter) = totp_params.counter {
+        if next || wait.is_some() {
+            return Err(anyhow::anyhow!(
+                "--next and --wait can't be used with hotp secrets"
+            ));
         }
+        let code = generate_code(&totp_params, counter)?;
+        // the counter is saved before displaying the code, so that the same
+        // code is never used twice
+        save_hotp_counter(&entry, field_index, &totp, counter + 1)?;
+        if verbose {
+            eprintln!("counter: {counter}");
+        }
+        code
     } else {
-        return Err(anyhow::anyhow!("not a login entry"));
-    }
+        let period = totp_params.period;
+        let mut now = unix_time()?;
+        let mut remaining = period - now % period;
+        if wait.is_some_and(|wait| remaining < wait) {
+            if verbose {
+                eprintln!("waiting {remaining}s for a fresh code");
+            }
+            std::thread::sleep(std::time::Duration::from_secs(remaining));
+            now = unix_time()?;
+            remaining = period - now % period;
+        }
+        if verbose {
+            if next {
+                eprintln!("valid in {remaining}s, for {period}s");
+            } else {
+                eprintln!("valid for {remaining}s");
+            }
+        }
+        generate_code(&totp_params, if next { now + period } else { now })?
+    };
+    val_display_or_store(clipboard, &code);
 
     Ok(())
 }
 
+pub fn totp_set(
+    needle: Needle,
+    user: Option<&str>,
+    folder: Option<&str>,
+    ignore_case: bool,
+    image: Option<&std::path::Path>,
+    yes: bool,
+) -> anyhow::Result<()> {
+    unlock()?;
+
+    let db = load_local_db()?;
+
+    let desc = format!(
+        "{}{}",
+        user.map_or_else(String::new, |s| format!("{s}@")),
+        needle
+    );
+
+    let (entry, decrypted) =
+        find_entry(&db, needle, user, folder, ignore_case)
+            .with_context(|| format!("couldn't find entry for '{desc}'"))?;
+    let DecryptedData::Login { totp, .. } = &decrypted.data else {
+        return Err(anyhow::anyhow!(
+            "totp secrets can only be set on login entries"
+        ));
+    };
+
+    let secret = if let Some(image) = image {
+        decode_qr_code(image)?
+    } else if std::io::stdin().is_terminal() {
+        let secret = rbw::tty::read_secret("otpauth uri or base32 secret: ")
+            .context("failed to read totp secret")?;
+        std::str::from_utf8(secret.password())
+            .context("totp secret was not valid utf8")?
+            .trim()
+            .to_string()
+    } else {
+        let mut secret = String::new();
+        std::io::stdin()
+            .read_line(&mut secret)
+            .context("failed to read totp secret")?;
+        secret.trim().to_string()
+    };
+    let totp_params = parse_totp_secret(&secret)?;
+    let code = generate_code(
+        &totp_params,
+        totp_params.counter.map_or_else(unix_time, Ok)?,
+    )?;
+    eprintln!("current code: {code}");
+
+    if !yes {
+        let prompt = if totp.is_some() {
+            format!("replace the totp secret for {desc}?")
+        } else {
+            format!("save this totp secret to {desc}?")
+        };
+        if !confirm(&prompt)? {
+            return Err(anyhow::anyhow!("totp secret not saved"));
+        }
+    }
+
+    save_totp(&entry, &secret)
+}
+
+// decodes the first qr code found in a png or jpeg image
+fn decode_qr_code(path: &std::path::Path) -> anyhow::Result<String> {
+    let image = image::ImageReader::open(path)
+        .with_context(|| format!("failed to open {}", path.display()))?
+        .with_guessed_format()
+        .with_context(|| format!("failed to read {}", path.display()))?
+        .decode()
+        .with_context(|| format!("failed to decode {}", path.display()))?
+        .to_luma8();
+    let width = usize::try_from(image.width())?;
+    let height = usize::try_from(image.height())?;
+    let pixels = image.as_raw();
+    let mut prepared =
+        rqrr::PreparedImage::prepare_from_greyscale(width, height, |x, y| {
+            pixels[y * width + x]
+        });
+    let grids = prepared.detect_grids();
+    let grid = grids.first().ok_or_else(|| {
+        anyhow::anyhow!("couldn't find a qr code in {}", path.display())
+    })?;
+    let (_, content) = grid
+        .decode()
+        .map_err(|e| anyhow::anyhow!("failed to decode qr code: {e}"))?;
+    Ok(content)
+}
+
+// finds the custom field with the given (case insensitive) name. partial
+// matches aren't allowed, since picking the wrong field would silently
+// generate codes from (and save hotp counters to) the wrong secret.
+fn find_field<'a>(
+    fields: &'a [DecryptedField],
+    name: &str,
+) -> anyhow::Result<(usize, &'a str)> {
+    let lower = name.to_lowercase();
+    let mut matches = fields.iter().enumerate().filter(|(_, field)| {
+        field
+            .name
+            .as_ref()
+            .is_some_and(|field_name| field_name.to_lowercase() == lower)
+    });
+    let Some((i, field)) = matches.next() else {
+        return Err(anyhow::anyhow!(
+            "entry does not contain a field '{name}'"
+        ));
+    };
+    if matches.next().is_some() {
+        return Err(anyhow::anyhow!(
+            "entry contains more than one field named '{name}'"
+        ));
+    }
+    let value = field
+        .value
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("field '{name}' is empty"))?;
+    Ok((i, value))
+}
+
+// saves the incremented counter either to the login's totp secret or to the
+// custom field at the given index, depending on where the secret came from
+fn save_hotp_counter(
+    entry: &rbw::db::Entry,
+    field: Option<usize>,
+    secret: &str,
+    counter: u64,
+) -> anyhow::Result<()> {
+    let secret = set_hotp_counter(secret, counter)?;
+    if let Some(i) = field {
+        let mut fields = entry.fields.clone();
+        fields[i].value =
+            Some(crate::actions::encrypt(&secret, entry.org_id.as_deref())?);
+        send_change(rbw::db::PendingAction::Edit {
+            entry: rbw::db::Entry {
+                fields,
+                ..entry.clone()
+            },
+            orig: entry.clone(),
+        })
+    } else {
+        save_totp(entry, &secret)
+    }
+}
+
+fn save_totp(entry: &rbw::db::Entry, secret: &str) -> anyhow::Result<()> {
+    let rbw::db::EntryData::Login {
+        username,
+        password,
+        uris,
+        ..
+    } = &entry.data
+    else {
+        unreachable!();
+    };
+    let totp = crate::actions::encrypt(secret, entry.org_id.as_deref())?;
+
+    send_change(rbw::db::PendingAction::Edit {
+        entry: rbw::db::Entry {
+            data: rbw::db::EntryData::Login {
+                username: username.clone(),
+                password: password.clone(),
+                uris: uris.clone(),
+                totp: Some(totp),
+            },
+            ..entry.clone()
+        },
+        orig: entry.clone(),
+    })
+}
+
 pub fn add(
     name: &str,
     username: Option<&str>,
@@ -1563,12 +2164,6 @@ pub fn add(
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let mut db = load_db()?;
-    // unwrap is safe here because the call to unlock above is guaranteed to
-    // populate these or error
-    let mut access_token = db.access_token.as_ref().unwrap().clone();
-    let refresh_token = db.refresh_token.as_ref().unwrap();
-
     let name = crate::actions::encrypt(name, None)?;
 
     let username = username
@@ -1594,62 +2189,17 @@ pub fn add(
         })
         .collect::<anyhow::Result<_>>()?;
 
-    let mut folder_id = None;
-    if let Some(folder_name) = folder {
-        let (new_access_token, folders) =
-            rbw::actions::list_folders(&access_token, refresh_token)?;
-        if let Some(new_access_token) = new_access_token {
-            access_token.clone_from(&new_access_token);
-            db.access_token = Some(new_access_token);
-            save_db(&db)?;
-        }
-
-        let folders: Vec<(String, String)> = folders
-            .iter()
-            .cloned()
-            .map(|(id, name)| {
-                Ok((id, crate::actions::decrypt(&name, None, None)?))
-            })
-            .collect::<anyhow::Result<_>>()?;
-
-        for (id, name) in folders {
-            if name == folder_name {
-                folder_id = Some(id);
-            }
-        }
-        if folder_id.is_none() {
-            let (new_access_token, id) = rbw::actions::create_folder(
-                &access_token,
-                refresh_token,
-                &crate::actions::encrypt(folder_name, None)?,
-            )?;
-            if let Some(new_access_token) = new_access_token {
-                access_token.clone_from(&new_access_token);
-                db.access_token = Some(new_access_token);
-                save_db(&db)?;
-            }
-            folder_id = Some(id);
-        }
-    }
-
-    if let (Some(access_token), ()) = rbw::actions::add(
-        &access_token,
-        refresh_token,
-        &name,
-        &rbw::db::EntryData::Login {
+    add_entry(
+        name,
+        rbw::db::EntryData::Login {
             username,
             password,
             uris,
             totp: None,
         },
-        notes.as_deref(),
-        folder_id.as_deref(),
-    )? {
-        db.access_token = Some(access_token);
-        save_db(&db)?;
-    }
-
-    crate::actions::sync()?;
+        notes,
+        folder,
+    )?;
 
     Ok(())
 }
@@ -1668,12 +2218,6 @@ pub fn generate(
     if let Some(name) = name {
         unlock()?;
 
-        let mut db = load_db()?;
-        // unwrap is safe here because the call to unlock above is guaranteed
-        // to populate these or error
-        let mut access_token = db.access_token.as_ref().unwrap().clone();
-        let refresh_token = db.refresh_token.as_ref().unwrap();
-
         let name = crate::actions::encrypt(name, None)?;
         let username = username
             .map(|username| crate::actions::encrypt(username, None))
@@ -1689,62 +2233,17 @@ pub fn generate(
             })
             .collect::<anyhow::Result<_>>()?;
 
-        let mut folder_id = None;
-        if let Some(folder_name) = folder {
-            let (new_access_token, folders) =
-                rbw::actions::list_folders(&access_token, refresh_token)?;
-            if let Some(new_access_token) = new_access_token {
-                access_token.clone_from(&new_access_token);
-                db.access_token = Some(new_access_token);
-                save_db(&db)?;
-            }
-
-            let folders: Vec<(String, String)> = folders
-                .iter()
-                .cloned()
-                .map(|(id, name)| {
-                    Ok((id, crate::actions::decrypt(&name, None, None)?))
-                })
-                .collect::<anyhow::Result<_>>()?;
-
-            for (id, name) in folders {
-                if name == folder_name {
-                    folder_id = Some(id);
-                }
-            }
-            if folder_id.is_none() {
-                let (new_access_token, id) = rbw::actions::create_folder(
-                    &access_token,
-                    refresh_token,
-                    &crate::actions::encrypt(folder_name, None)?,
-                )?;
-                if let Some(new_access_token) = new_access_token {
-                    access_token.clone_from(&new_access_token);
-                    db.access_token = Some(new_access_token);
-                    save_db(&db)?;
-                }
-                folder_id = Some(id);
-            }
-        }
-
-        if let (Some(access_token), ()) = rbw::actions::add(
-            &access_token,
-            refresh_token,
-            &name,
-            &rbw::db::EntryData::Login {
+        add_entry(
+            name,
+            rbw::db::EntryData::Login {
                 username,
                 password: Some(password),
                 uris,
                 totp: None,
             },
             None,
-            folder_id.as_deref(),
-        )? {
-            db.access_token = Some(access_token);
-            save_db(&db)?;
-        }
-
-        crate::actions::sync()?;
+            folder,
+        )?;
     }
 
     Ok(())
@@ -1758,10 +2257,6 @@ pub fn edit(
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let mut db = load_db()?;
-    let access_token = db.access_token.as_ref().unwrap();
-    let refresh_token = db.refresh_token.as_ref().unwrap();
-
     let desc = format!(
         "{}{}",
         username.map_or_else(String::new, |s| format!("{s}@")),
@@ -1769,7 +2264,7 @@ pub fn edit(
     );
 
     let (entry, decrypted) =
-        find_entry(&db, name, username, folder, ignore_case)
+        find_entry(&load_local_db()?, name, username, folder, ignore_case)
             .with_context(|| format!("couldn't find entry for '{desc}'"))?;
 
     let (data, fields, notes, history) = match &decrypted.data {
@@ -1826,7 +2321,7 @@ pub fn edit(
                 uris: entry_uris.clone(),
                 totp: entry_totp.clone(),
             };
-            (data, entry.fields, notes, history)
+            (data, entry.fields.clone(), notes, history)
         }
         DecryptedData::SecureNote => {
             let data = rbw::db::EntryData::SecureNote {};
@@ -1846,7 +2341,7 @@ pub fn edit(
                 })
                 .transpose()?;
 
-            (data, entry.fields, notes, entry.history)
+            (data, entry.fields.clone(), notes, entry.history.clone())
         }
         _ => {
             return Err(anyhow::anyhow!(
@@ -1855,24 +2350,16 @@ pub fn edit(
         }
     };
 
-    if let (Some(access_token), ()) = rbw::actions::edit(
-        access_token,
-        refresh_token,
-        &entry.id,
-        entry.org_id.as_deref(),
-        &entry.name,
-        &data,
-        &fields,
-        notes.as_deref(),
-        entry.folder_id.as_deref(),
-        &history,
-    )? {
-        db.access_token = Some(access_token);
-        save_db(&db)?;
-    }
-
-    crate::actions::sync()?;
-    Ok(())
+    send_change(rbw::db::PendingAction::Edit {
+        entry: rbw::db::Entry {
+            data,
+            fields,
+            notes,
+            history,
+            ..entry.clone()
+        },
+        orig: entry,
+    })
 }
 
 pub fn remove(
@@ -1883,9 +2370,28 @@ pub fn remove(
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let mut db = load_db()?;
-    let access_token = db.access_token.as_ref().unwrap();
-    let refresh_token = db.refresh_token.as_ref().unwrap();
+    let desc = format!(
+        "{}{}",
+        username.map_or_else(String::new, |s| format!("{s}@")),
+        name
+    );
+
+    let (entry, _) =
+        find_entry(&load_local_db()?, name, username, folder, ignore_case)
+            .with_context(|| format!("couldn't find entry for '{desc}'"))?;
+
+    send_change(rbw::db::PendingAction::Remove { orig: entry })
+}
+
+pub fn history(
+    name: Needle,
+    username: Option<&str>,
+    folder: Option<&str>,
+    ignore_case: bool,
+) -> anyhow::Result<()> {
+    unlock()?;
+
+    let db = load_local_db()?;
 
     let desc = format!(
         "{}{}",
@@ -1893,22 +2399,77 @@ pub fn remove(
         name
     );
 
-    let (entry, _) = find_entry(&db, name, username, folder, ignore_case)
+    let (_, decrypted) = find_entry(&db, name, username, folder, ignore_case)
         .with_context(|| format!("couldn't find entry for '{desc}'"))?;
-
-    if let (Some(access_token), ()) =
-        rbw::actions::remove(access_token, refresh_token, &entry.id)?
-    {
-        db.access_token = Some(access_token);
-        save_db(&db)?;
+    for history in decrypted.history {
+        println!("{}: {}", history.last_used_date, history.password);
     }
 
-    crate::actions::sync()?;
+    Ok(())
+}
+
+pub fn ssh_key_generate(
+    name: &str,
+    algorithm: ssh_agent_lib::ssh_key::Algorithm,
+    folder: Option<&str>,
+) -> anyhow::Result<()> {
+    unlock()?;
+
+    // the only rsa key type offered is rsa-4096, so the size is given
+    // explicitly rather than relying on ssh-key's default
+    let private_key =
+        if matches!(algorithm, ssh_agent_lib::ssh_key::Algorithm::Rsa { .. })
+        {
+            ssh_agent_lib::ssh_key::private::RsaKeypair::random(
+                &mut rand_8::rngs::OsRng,
+                4096,
+            )
+            .and_then(|keypair| {
+                ssh_agent_lib::ssh_key::PrivateKey::new(
+                    ssh_agent_lib::ssh_key::private::KeypairData::Rsa(
+                        keypair,
+                    ),
+                    "",
+                )
+            })
+        } else {
+            ssh_agent_lib::ssh_key::PrivateKey::random(
+                &mut rand_8::rngs::OsRng,
+                algorithm,
+            )
+        }
+        .context("failed to generate ssh key")?;
+    let public_key = private_key.public_key().to_openssh()?;
+    let fingerprint = private_key
+        .public_key()
+        .fingerprint(ssh_agent_lib::ssh_key::HashAlg::Sha256)
+        .to_string();
+    let private_key =
+        private_key.to_openssh(ssh_agent_lib::ssh_key::LineEnding::LF)?;
+
+    let encrypted_name = crate::actions::encrypt(name, None)?;
+    let encrypted_private_key = crate::actions::encrypt(&private_key, None)?;
+    let encrypted_public_key = crate::actions::encrypt(&public_key, None)?;
+    let encrypted_fingerprint = crate::actions::encrypt(&fingerprint, None)?;
+
+    add_entry(
+        encrypted_name,
+        rbw::db::EntryData::SshKey {
+            private_key: Some(encrypted_private_key),
+            public_key: Some(encrypted_public_key),
+            fingerprint: Some(encrypted_fingerprint),
+        },
+        None,
+        folder,
+    )?;
+
+    println!("{fingerprint} {name}");
+    println!("{public_key}");
 
     Ok(())
 }
 
-pub fn history(
+pub fn ssh_key_public(
     name: Needle,
     username: Option<&str>,
     folder: Option<&str>,
@@ -1916,7 +2477,7 @@ pub fn history(
 ) -> anyhow::Result<()> {
     unlock()?;
 
-    let db = load_db()?;
+    let db = load_local_db()?;
 
     let desc = format!(
         "{}{}",
@@ -1926,8 +2487,43 @@ pub fn history(
 
     let (_, decrypted) = find_entry(&db, name, username, folder, ignore_case)
         .with_context(|| format!("couldn't find entry for '{desc}'"))?;
-    for history in decrypted.history {
-        println!("{}: {}", history.last_used_date, history.password);
+    let DecryptedData::SshKey { public_key, .. } = decrypted.data else {
+        return Err(anyhow::anyhow!("entry for '{desc}' is not an ssh key"));
+    };
+    let public_key = public_key.ok_or_else(|| {
+        anyhow::anyhow!("entry for '{desc}' had no public key")
+    })?;
+    println!("{public_key}");
+
+    Ok(())
+}
+
+pub fn log(lines: Option<usize>, raw: bool) -> anyhow::Result<()> {
+    let records = rbw::audit::load()?;
+    let skip = lines.map_or(0, |lines| records.len().saturating_sub(lines));
+
+    for record in records.iter().skip(skip) {
+        if raw {
+            serde_json::to_writer(std::io::stdout(), record)
+                .context("failed to write audit log record to stdout")?;
+            println!();
+        } else {
+            let client = match (&record.exe, record.pid) {
+                (Some(exe), Some(pid)) => {
+                    format!("{} (pid {pid})", exe.display())
+                }
+                (None, Some(pid)) => format!("pid {pid}"),
+                (Some(exe), None) => exe.display().to_string(),
+                (None, None) => "-".to_string(),
+            };
+            println!(
+                "{}\t{}\t{}\t{}\t{client}",
+                record.timestamp,
+                record.action,
+                record.outcome,
+                record.entry_id.as_deref().unwrap_or("-"),
+            );
+        }
     }
 
     Ok(())
@@ -1944,16 +2540,33 @@ pub fn purge() -> anyhow::Result<()> {
     stop_agent()?;
 
     remove_db()?;
+    // there is no pin to remove if no email was ever configured
+    let config = rbw::config::Config::load()?;
+    if let Some(email) = &config.email {
+        rbw::pin::Pin::remove(&config.server_name(), email)?;
+    }
 
     Ok(())
 }
 
 pub fn stop_agent() -> anyhow::Result<()> {
     crate::actions::quit()?;
+    forget_keyring();
 
     Ok(())
 }
 
+// the agent leaves its keys in the session keyring when it exits, so that an
+// agent restarted after an upgrade (see check_agent_version) can pick up
+// where it left off. explicitly stopping the agent (including via purge, or
+// when the config changes) should make the next agent ask for the master
+// password again, though.
+fn forget_keyring() {
+    if let Err(e) = rbw::keyring::remove() {
+        log::warn!("failed to remove keys from session keyring: {e:#}");
+    }
+}
+
 fn ensure_agent() -> anyhow::Result<()> {
     check_config()?;
     if matches!(check_agent_version(), Ok(())) {
@@ -1996,6 +2609,8 @@ fn check_agent_version() -> anyhow::Result<()> {
     let client_version = rbw::protocol::VERSION;
     let agent_version = version_or_quit()?;
     if agent_version != client_version {
+        // not stop_agent, since the new agent should be able to resume from
+        // the session keyring
         crate::actions::quit()?;
         return Err(anyhow::anyhow!(
             "client protocol version is {client_version} but agent protocol version is {agent_version}"
@@ -2010,6 +2625,454 @@ fn version_or_quit() -> anyhow::Result<u32> {
     })
 }
 
+const QUEUED: &str = "couldn't connect to the server, so this change will \
+    be sent the next time the database is synced";
+
+// adds a new entry on the server, or queues it to be added on the next sync
+// if the server can't be reached. the name, data and notes must already be
+// encrypted.
+fn add_entry(
+    name: String,
+    data: rbw::db::EntryData,
+    notes: Option<String>,
+    folder: Option<&str>,
+) -> anyhow::Result<()> {
+    let lock = lock_db()?;
+    let mut db = load_db()?;
+
+    // unwrap is safe here because the call to unlock before this is
+    // guaranteed to populate these or error
+    let mut access_token = db.access_token.as_ref().unwrap().clone();
+    let refresh_token = db.refresh_token.as_ref().unwrap().clone();
+
+    let folder_id = match folder
+        .map(|folder_name| {
+            find_or_create_folder(
+                &mut db,
+                &lock,
+                &mut access_token,
+                &refresh_token,
+                folder_name,
+            )
+        })
+        .transpose()
+    {
+        Ok(folder_id) => folder_id,
+        Err(e) if is_offline(&e) => {
+            return queue_add(&mut db, &lock, name, data, notes, folder);
+        }
+        Err(e) => return Err(e),
+    };
+
+    match rbw::actions::add(
+        &access_token,
+        &refresh_token,
+        &name,
+        &data,
+        notes.as_deref(),
+        folder_id.as_deref(),
+    ) {
+        Ok((Some(access_token), ())) => {
+            db.access_token = Some(access_token);
+            save_db(&db, &lock)?;
+        }
+        Ok((None, ())) => {}
+        Err(e) if e.is_offline() => {
+            return queue_add(&mut db, &lock, name, data, notes, folder);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    drop(lock);
+    crate::actions::sync()?;
+
+    Ok(())
+}
+
+fn queue_add(
+    db: &mut rbw::db::Db,
+    lock: &rbw::db::Lock,
+    name: String,
+    data: rbw::db::EntryData,
+    notes: Option<String>,
+    folder: Option<&str>,
+) -> anyhow::Result<()> {
+    let (folder_id, folder) = if let Some(folder_name) = folder {
+        let (id, name) =
+            find_local_folder(db, folder_name)?.with_context(|| {
+                format!(
+                    "couldn't find folder '{folder_name}' in the local \
+                    database, and folders can't be created while offline"
+                )
+            })?;
+        (Some(id), Some(name))
+    } else {
+        (None, None)
+    };
+
+    db.queue(rbw::db::PendingAction::Add {
+        entry: rbw::db::Entry {
+            id: uuid::Uuid::new_v4().to_string(),
+            org_id: None,
+            folder,
+            folder_id,
+            name,
+            data,
+            fields: vec![],
+            notes,
+            history: vec![],
+            key: None,
+            master_password_reprompt: rbw::api::CipherRepromptType::None,
+            revision_date: None,
+        },
+    });
+    save_db(db, lock)?;
+    eprintln!("{QUEUED}");
+
+    Ok(())
+}
+
+// sends an edit or removal to the server, or queues it to be sent on the
+// next sync if the server can't be reached (or if there are already
+// changes to the entry waiting to be sent)
+fn send_change(action: rbw::db::PendingAction) -> anyhow::Result<()> {
+    let lock = lock_db()?;
+    let mut db = load_db()?;
+
+    if db.pending(action.id()).is_some() {
+        db.queue(action);
+        save_db(&db, &lock)?;
+        eprintln!(
+            "this entry has changes which haven't been sent to the server \
+            yet, so this change will be sent the next time the database is \
+            synced"
+        );
+        return Ok(());
+    }
+
+    // unwrap is safe here because the call to unlock before this is
+    // guaranteed to populate these or error
+    let access_token = db.access_token.as_ref().unwrap();
+    let refresh_token = db.refresh_token.as_ref().unwrap();
+    let res = match &action {
+        rbw::db::PendingAction::Edit { entry, .. } => rbw::actions::edit(
+            access_token,
+            refresh_token,
+            &entry.id,
+            entry.org_id.as_deref(),
+            &entry.name,
+            &entry.data,
+            &entry.fields,
+            entry.notes.as_deref(),
+            entry.folder_id.as_deref(),
+            &entry.history,
+            entry.revision_date.as_deref(),
+        ),
+        rbw::db::PendingAction::Remove { orig } => {
+            rbw::actions::remove(access_token, refresh_token, &orig.id)
+        }
+        rbw::db::PendingAction::Add { .. } => unreachable!(),
+    };
+    match res {
+        Ok((Some(access_token), ())) => {
+            db.access_token = Some(access_token);
+            save_db(&db, &lock)?;
+        }
+        Ok((None, ())) => {}
+        Err(e) if e.is_offline() => {
+            db.queue(action);
+            save_db(&db, &lock)?;
+            eprintln!("{QUEUED}");
+            return Ok(());
+        }
+        Err(rbw::error::Error::CipherOutOfDate) => {
+            let rbw::db::PendingAction::Edit { entry, orig } = action else {
+                unreachable!()
+            };
+            // resolving the conflict syncs, which needs the lock
+            drop(lock);
+            return resolve_conflict(&entry, &orig);
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    drop(lock);
+    crate::actions::sync()?;
+
+    Ok(())
+}
+
+// called when an edit was refused because the entry was modified on the
+// server since it was last synced. shows what changed on each side, and
+// lets the user either apply their changes on top of the server's version
+// or abort.
+fn resolve_conflict(
+    ours: &rbw::db::Entry,
+    orig: &rbw::db::Entry,
+) -> anyhow::Result<()> {
+    crate::actions::sync()?;
+    let db = load_db()?;
+    let theirs = db
+        .entries
+        .iter()
+        .find(|entry| entry.id == ours.id)
+        .cloned()
+        .context("the entry was deleted on the server")?;
+
+    let orig_fields = decrypt_cipher(orig)?.diff_fields();
+    eprintln!(
+        "the entry was modified on the server since it was last synced"
+    );
+    eprintln!("changes on the server:");
+    print_diff(&orig_fields, &decrypt_cipher(&theirs)?.diff_fields());
+    eprintln!("your changes:");
+    print_diff(&orig_fields, &decrypt_cipher(ours)?.diff_fields());
+
+    if !confirm("merge your changes into the server's version?")? {
+        return Err(anyhow::anyhow!("edit aborted"));
+    }
+
+    let merged = merge_edit(ours, orig, &theirs)?;
+    send_change(rbw::db::PendingAction::Edit {
+        entry: merged,
+        orig: theirs,
+    })
+}
+
+fn confirm(prompt: &str) -> anyhow::Result<bool> {
+    eprint!("{prompt} [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("failed to read answer")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+fn print_diff(
+    old: &[(String, String, bool)],
+    new: &[(String, String, bool)],
+) {
+    let mut labels: Vec<&str> = vec![];
+    for (label, _, _) in old.iter().chain(new) {
+        if !labels.contains(&label.as_str()) {
+            labels.push(label);
+        }
+    }
+
+    let values = |fields: &[(String, String, bool)], label: &str| {
+        fields
+            .iter()
+            .filter(|(l, _, _)| l == label)
+            .map(|(_, value, secret)| (value.clone(), *secret))
+            .collect::<Vec<_>>()
+    };
+    let describe = |values: &[(String, bool)]| {
+        if values.is_empty() {
+            "(none)".to_string()
+        } else {
+            values
+                .iter()
+                .map(|(value, _)| format!("{value:?}"))
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+    };
+
+    let mut changed = false;
+    for label in labels {
+        let old = values(old, label);
+        let new = values(new, label);
+        if old == new {
+            continue;
+        }
+        changed = true;
+        if old.iter().chain(&new).any(|(_, secret)| *secret) {
+            eprintln!("    {label}: changed");
+        } else {
+            eprintln!(
+                "    {label}: {} -> {}",
+                describe(&old),
+                describe(&new)
+            );
+        }
+    }
+    if !changed {
+        eprintln!("    (none)");
+    }
+}
+
+// applies the changes that rbw can make to an existing entry (the
+// password, notes, totp secret and custom field values) on top of the
+// server's version of the entry
+fn merge_edit(
+    ours: &rbw::db::Entry,
+    orig: &rbw::db::Entry,
+    theirs: &rbw::db::Entry,
+) -> anyhow::Result<rbw::db::Entry> {
+    let ours_decrypted = decrypt_cipher(ours)?;
+    let orig_decrypted = decrypt_cipher(orig)?;
+    let mut merged = theirs.clone();
+
+    if let (
+        DecryptedData::Login {
+            password: ours_password,
+            totp: ours_totp,
+            ..
+        },
+        DecryptedData::Login {
+            password: orig_password,
+            totp: orig_totp,
+            ..
+        },
+        rbw::db::EntryData::Login {
+            password: ours_encrypted,
+            totp: ours_totp_encrypted,
+            ..
+        },
+        rbw::db::EntryData::Login { password, totp, .. },
+    ) = (
+        &ours_decrypted.data,
+        &orig_decrypted.data,
+        &ours.data,
+        &mut merged.data,
+    ) {
+        if ours_password != orig_password {
+            if let Some(prev_password) = password.take() {
+                merged.history.insert(
+                    0,
+                    rbw::db::HistoryEntry {
+                        last_used_date: format!(
+                            "{}",
+                            humantime::format_rfc3339(
+                                std::time::SystemTime::now()
+                            )
+                        ),
+                        password: prev_password,
+                    },
+                );
+            }
+            password.clone_from(ours_encrypted);
+        }
+        // also where hotp counters are stored
+        if ours_totp != orig_totp {
+            totp.clone_from(ours_totp_encrypted);
+        }
+    }
+    if ours_decrypted.notes != orig_decrypted.notes {
+        merged.notes.clone_from(&ours.notes);
+    }
+    if ours_decrypted.fields != orig_decrypted.fields {
+        merge_fields(
+            &mut merged,
+            ours,
+            &ours_decrypted.fields,
+            &orig_decrypted.fields,
+            &decrypt_cipher(theirs)?.fields,
+        )?;
+    }
+
+    Ok(merged)
+}
+
+// rbw only ever changes the values of existing custom fields (hotp counters
+// in particular), so those are the only changes that can be merged. the
+// decrypted fields are in the same order as the encrypted ones.
+fn merge_fields(
+    merged: &mut rbw::db::Entry,
+    ours: &rbw::db::Entry,
+    ours_fields: &[DecryptedField],
+    orig_fields: &[DecryptedField],
+    theirs_fields: &[DecryptedField],
+) -> anyhow::Result<()> {
+    if theirs_fields == orig_fields {
+        merged.fields.clone_from(&ours.fields);
+        return Ok(());
+    }
+    if ours_fields.len() != orig_fields.len() {
+        return Err(anyhow::anyhow!(
+            "can't merge custom fields which were added or removed"
+        ));
+    }
+
+    for (i, (ours_field, orig_field)) in
+        ours_fields.iter().zip(orig_fields).enumerate()
+    {
+        if ours_field == orig_field {
+            continue;
+        }
+        let mut matches = theirs_fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.name == orig_field.name);
+        let (Some((j, _)), None) = (matches.next(), matches.next()) else {
+            return Err(anyhow::anyhow!(
+                "can't merge changes to custom field '{}'",
+                orig_field.name.as_deref().unwrap_or("")
+            ));
+        };
+        merged.fields[j].clone_from(&ours.fields[i]);
+    }
+
+    Ok(())
+}
+
+fn is_offline(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<rbw::error::Error>()
+        .is_some_and(rbw::error::Error::is_offline)
+}
+
+// returns the id and encrypted name of a folder which is already used by
+// an entry in the local database
+fn find_local_folder(
+    db: &rbw::db::Db,
+    folder_name: &str,
+) -> anyhow::Result<Option<(String, String)>> {
+    for entry in &db.entries {
+        if let (Some(id), Some(name)) = (&entry.folder_id, &entry.folder) {
+            if crate::actions::decrypt(name, None, None)? == folder_name {
+                return Ok(Some((id.clone(), name.clone())));
+            }
+        }
+    }
+    Ok(None)
+}
+
+// returns the id of the folder with the given name, creating it if it
+// doesn't exist yet
+fn find_or_create_folder(
+    db: &mut rbw::db::Db,
+    lock: &rbw::db::Lock,
+    access_token: &mut String,
+    refresh_token: &str,
+    folder_name: &str,
+) -> anyhow::Result<String> {
+    let (new_access_token, folders) =
+        rbw::actions::list_folders(access_token, refresh_token)?;
+    if let Some(new_access_token) = new_access_token {
+        access_token.clone_from(&new_access_token);
+        db.access_token = Some(new_access_token);
+        save_db(db, lock)?;
+    }
+
+    for (id, name) in folders {
+        if crate::actions::decrypt(&name, None, None)? == folder_name {
+            return Ok(id);
+        }
+    }
+
+    let (new_access_token, id) = rbw::actions::create_folder(
+        access_token,
+        refresh_token,
+        &crate::actions::encrypt(folder_name, None)?,
+    )?;
+    if let Some(new_access_token) = new_access_token {
+        access_token.clone_from(&new_access_token);
+        db.access_token = Some(new_access_token);
+        save_db(db, lock)?;
+    }
+    Ok(id)
+}
+
 fn find_entry(
     db: &rbw::db::Db,
     mut needle: Needle,
@@ -2026,14 +3089,7 @@ fn find_entry(
         needle = Needle::Name(s);
     }
 
-    let ciphers: Vec<(rbw::db::Entry, DecryptedSearchCipher)> = db
-        .entries
-        .iter()
-        .map(|entry| {
-            decrypt_search_cipher(entry)
-                .map(|decrypted| (entry.clone(), decrypted))
-        })
-        .collect::<anyhow::Result<_>>()?;
+    let ciphers = search_ciphers(&db.entries)?;
     let (entry, _) =
         find_entry_raw(&ciphers, &needle, username, folder, ignore_case)?;
     let decrypted_entry = decrypt_cipher(&entry)?;
@@ -2122,82 +3178,26 @@ fn decrypt_field(
     }
 }
 
-fn decrypt_list_cipher(
-    entry: &rbw::db::Entry,
-    fields: &[ListField],
-) -> anyhow::Result<DecryptedListCipher> {
-    let id = entry.id.clone();
-    let name = if fields.contains(&ListField::Name) {
-        Some(crate::actions::decrypt(
-            &entry.name,
-            entry.key.as_deref(),
-            entry.org_id.as_deref(),
-        )?)
-    } else {
-        None
-    };
-    let user = if fields.contains(&ListField::User) {
-        match &entry.data {
-            rbw::db::EntryData::Login { username, .. } => decrypt_field(
-                Field::Username,
-                username.as_deref(),
-                entry.key.as_deref(),
-                entry.org_id.as_deref(),
-            ),
-            _ => None,
-        }
-    } else {
-        None
-    };
-    let folder = if fields.contains(&ListField::Folder) {
-        // folder name should always be decrypted with the local key because
-        // folders are local to a specific user's vault, not the organization
-        entry
-            .folder
-            .as_ref()
-            .map(|folder| crate::actions::decrypt(folder, None, None))
-            .transpose()?
-    } else {
-        None
-    };
-    let uris = if fields.contains(&ListField::Uri) {
-        match &entry.data {
-            rbw::db::EntryData::Login { uris, .. } => Some(
-                uris.iter()
-                    .filter_map(|s| {
-                        decrypt_field(
-                            Field::Uris,
-                            Some(&s.uri),
-                            entry.key.as_deref(),
-                            entry.org_id.as_deref(),
-                        )
-                    })
-                    .collect(),
-            ),
-            _ => None,
-        }
-    } else {
-        None
-    };
-    let entry_type = fields
-        .contains(&ListField::EntryType)
-        .then_some(match &entry.data {
-            rbw::db::EntryData::Login { .. } => "Login",
-            rbw::db::EntryData::Identity { .. } => "Identity",
-            rbw::db::EntryData::SshKey { .. } => "SSH Key",
-            rbw::db::EntryData::SecureNote => "Note",
-            rbw::db::EntryData::Card { .. } => "Card",
+// the searchable metadata for each entry comes from the agent's search
+// index, falling back to decrypting it directly for any entries which the
+// index doesn't know about yet
+fn search_ciphers(
+    entries: &[rbw::db::Entry],
+) -> anyhow::Result<Vec<(rbw::db::Entry, DecryptedSearchCipher)>> {
+    let mut index: std::collections::HashMap<_, _> = crate::actions::index()?
+        .into_iter()
+        .map(|entry| (entry.id.clone(), entry))
+        .collect();
+    entries
+        .iter()
+        .map(|entry| {
+            let decrypted = match index.remove(&entry.id) {
+                Some(indexed) => indexed.into(),
+                None => decrypt_search_cipher(entry)?,
+            };
+            Ok((entry.clone(), decrypted))
         })
-        .map(str::to_string);
-
-    Ok(DecryptedListCipher {
-        id,
-        name,
-        user,
-        folder,
-        uris,
-        entry_type,
-    })
+        .collect()
 }
 
 fn decrypt_search_cipher(
@@ -2269,6 +3269,18 @@ fn decrypt_search_cipher(
             )
         })
         .collect::<anyhow::Result<_>>()?;
+    let field_names = entry
+        .fields
+        .iter()
+        .filter_map(|field| {
+            decrypt_field(
+                Field::Name,
+                field.name.as_deref(),
+                entry.key.as_deref(),
+                entry.org_id.as_deref(),
+            )
+        })
+        .collect();
     let notes = match notes {
         Ok(notes) => notes,
         Err(e) => {
@@ -2276,14 +3288,7 @@ fn decrypt_search_cipher(
             None
         }
     };
-    let entry_type = (match &entry.data {
-        rbw::db::EntryData::Login { .. } => "Login",
-        rbw::db::EntryData::Identity { .. } => "Identity",
-        rbw::db::EntryData::SshKey { .. } => "SSH Key",
-        rbw::db::EntryData::SecureNote => "Note",
-        rbw::db::EntryData::Card { .. } => "Card",
-    })
-    .to_string();
+    let entry_type = entry.data.type_name().to_string();
 
     Ok(DecryptedSearchCipher {
         id,
@@ -2293,6 +3298,7 @@ fn decrypt_search_cipher(
         user,
         uris,
         fields,
+        field_names,
         notes,
     })
 }
@@ -2658,12 +3664,39 @@ fn load_db() -> anyhow::Result<rbw::db::Db> {
     )
 }
 
-fn save_db(db: &rbw::db::Db) -> anyhow::Result<()> {
+// the local database as it will be once any changes made while offline have
+// been sent to the server. this shouldn't be saved.
+fn load_local_db() -> anyhow::Result<rbw::db::Db> {
+    let mut db = load_db()?;
+    db.apply_pending();
+    Ok(db)
+}
+
+// the agent also writes the db when syncing, so this should be held from
+// loading the db until saving it. it must be released before asking the
+// agent to sync, though. the agent holds it while sending queued changes to
+// the server, so this gives up rather than hanging along with the server.
+fn lock_db() -> anyhow::Result<rbw::db::Lock> {
+    let config = rbw::config::Config::load()?;
+    config.email.as_ref().map_or_else(
+        || Err(anyhow::anyhow!("failed to find email address in config")),
+        |email| {
+            rbw::db::Db::lock_timeout(
+                &config.server_name(),
+                email,
+                DB_LOCK_TIMEOUT,
+            )
+            .map_err(anyhow::Error::new)
+        },
+    )
+}
+
+fn save_db(db: &rbw::db::Db, lock: &rbw::db::Lock) -> anyhow::Result<()> {
     let config = rbw::config::Config::load()?;
     config.email.as_ref().map_or_else(
         || Err(anyhow::anyhow!("failed to find email address in config")),
         |email| {
-            db.save(&config.server_name(), email)
+            db.save(&config.server_name(), email, config.db_backups, lock)
                 .map_err(anyhow::Error::new)
         },
     )
@@ -2685,6 +3718,8 @@ struct TotpParams {
     algorithm: String,
     digits: usize,
     period: u64,
+    // only set for hotp secrets
+    counter: Option<u64>,
 }
 
 fn decode_totp_secret(secret: &str) -> anyhow::Result<Vec<u8>> {
@@ -2707,11 +3742,15 @@ fn parse_totp_secret(secret: &str) -> anyhow::Result<TotpParams> {
     if let Ok(u) = url::Url::parse(secret) {
         match u.scheme() {
             "otpauth" => {
-                if u.host_str() != Some("totp") {
-                    return Err(anyhow::anyhow!(
-                        "totp secret url must have totp host"
-                    ));
-                }
+                let hotp = match u.host_str() {
+                    Some("totp") => false,
+                    Some("hotp") => true,
+                    _ => {
+                        return Err(anyhow::anyhow!(
+                            "totp secret url must have totp or hotp host"
+                        ));
+                    }
+                };
 
                 let query: std::collections::HashMap<_, _> =
                     u.query_pairs().collect();
@@ -2737,12 +3776,33 @@ fn parse_totp_secret(secret: &str) -> anyhow::Result<TotpParams> {
                     }
                     None => TOTP_DEFAULT_STEP,
                 };
+                let counter = if hotp {
+                    Some(
+                        query
+                            .get("counter")
+                            .ok_or_else(|| {
+                                anyhow::anyhow!(
+                                    "hotp secret url must have counter"
+                                )
+                            })?
+                            .parse::<u64>()
+                            .map_err(|_| {
+                                anyhow::anyhow!(
+                                    "counter parameter in hotp url must be a \
+                                    valid integer."
+                                )
+                            })?,
+                    )
+                } else {
+                    None
+                };
 
                 Ok(TotpParams {
                     secret,
                     algorithm,
                     digits,
                     period,
+                    counter,
                 })
             }
             "steam" => {
@@ -2753,6 +3813,7 @@ fn parse_totp_secret(secret: &str) -> anyhow::Result<TotpParams> {
                     algorithm: String::from("STEAM"),
                     digits: 5,
                     period: TOTP_DEFAULT_STEP,
+                    counter: None,
                 })
             }
             _ => Err(anyhow::anyhow!(
@@ -2765,6 +3826,7 @@ fn parse_totp_secret(secret: &str) -> anyhow::Result<TotpParams> {
             algorithm: String::from("SHA1"),
             digits: 6,
             period: TOTP_DEFAULT_STEP,
+            counter: None,
         })
     }
 }
@@ -2785,25 +3847,71 @@ fn generate_totp_algorithm_type(
 
 fn generate_totp(secret: &str) -> anyhow::Result<String> {
     let totp_params = parse_totp_secret(secret)?;
+    if totp_params.counter.is_some() {
+        return Err(anyhow::anyhow!(
+            "hotp codes can only be generated with rbw code, since the \
+            counter needs to be updated"
+        ));
+    }
+    generate_code(&totp_params, unix_time()?)
+}
+
+// generates the code for the given time (or, for hotp secrets, for the
+// counter stored in the secret)
+fn generate_code(
+    totp_params: &TotpParams,
+    time: u64,
+) -> anyhow::Result<String> {
     let alg = totp_params.algorithm.as_str();
 
     match alg {
-        "SHA1" | "SHA256" | "SHA512" => Ok(totp_rs::TOTP::new_unchecked(
-            generate_totp_algorithm_type(alg)?,
-            totp_params.digits,
-            1, // the library docs say this should be a 1
-            totp_params.period,
-            totp_params.secret,
-        )
-        .generate_current()?),
-        "STEAM" => Ok(totp_rs::TOTP::new_steam(totp_params.secret)
-            .generate_current()?),
+        "SHA1" | "SHA256" | "SHA512" => {
+            // hotp is equivalent to totp with a one second period, evaluated
+            // at the counter
+            let (period, time) = totp_params
+                .counter
+                .map_or((totp_params.period, time), |counter| (1, counter));
+            Ok(totp_rs::TOTP::new_unchecked(
+                generate_totp_algorithm_type(alg)?,
+                totp_params.digits,
+                1, // the library docs say this should be a 1
+                period,
+                totp_params.secret.clone(),
+            )
+            .generate(time))
+        }
+        "STEAM" => Ok(totp_rs::TOTP::new_steam(totp_params.secret.clone())
+            .generate(time)),
         _ => Err(anyhow::anyhow!(format!(
             "{alg} is not a valid totp algorithm"
         ))),
     }
 }
 
+fn unix_time() -> anyhow::Result<u64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs())
+}
+
+// returns the hotp secret url with its counter replaced
+fn set_hotp_counter(secret: &str, counter: u64) -> anyhow::Result<String> {
+    let mut url = url::Url::parse(secret)?;
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(key, value)| {
+            let value = if key == "counter" {
+                counter.to_string()
+            } else {
+                value.into_owned()
+            };
+            (key.into_owned(), value)
+        })
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(query);
+    Ok(url.to_string())
+}
+
 fn display_field(name: &str, field: Option<&str>, clipboard: bool) -> bool {
     field.map_or_else(
         || false,
@@ -4050,6 +5158,53 @@ mod test {
         assert!(decoded == want, "strips spaces");
     }
 
+    #[test]
+    fn test_hotp() {
+        // test vectors from rfc 4226
+        let secret = "otpauth://hotp/test?secret=\
+            GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=1&issuer=test";
+        let params = parse_totp_secret(secret).unwrap();
+        assert_eq!(params.counter, Some(1));
+        assert_eq!(generate_code(&params, 0).unwrap(), "287082");
+        assert!(generate_totp(secret).is_err());
+
+        let secret = set_hotp_counter(secret, 9).unwrap();
+        assert_eq!(
+            secret,
+            "otpauth://hotp/test?secret=\
+            GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&counter=9&issuer=test"
+        );
+        let params = parse_totp_secret(&secret).unwrap();
+        assert_eq!(generate_code(&params, 0).unwrap(), "520489");
+
+        assert!(parse_totp_secret(
+            "otpauth://hotp/test?secret=GEZDGNBVGY3TQOJQ"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_find_field() {
+        let field = |name: &str, value: Option<&str>| DecryptedField {
+            name: Some(name.to_string()),
+            value: value.map(str::to_string),
+            ty: Some(rbw::api::FieldType::Hidden),
+        };
+        let fields = [
+            field("TOTP admin", Some("admin")),
+            field("totp", Some("user")),
+            field("recovery", None),
+            field("backup", Some("one")),
+            field("Backup", Some("two")),
+        ];
+        assert_eq!(find_field(&fields, "TOTP").unwrap(), (1, "user"));
+        assert_eq!(find_field(&fields, "totp admin").unwrap(), (0, "admin"));
+        assert!(find_field(&fields, "admin").is_err());
+        assert!(find_field(&fields, "recovery").is_err());
+        assert!(find_field(&fields, "backup").is_err());
+        assert!(find_field(&fields, "missing").is_err());
+    }
+
     #[track_caller]
     fn one_match(
         entries: &[(rbw::db::Entry, DecryptedSearchCipher)],
@@ -4157,6 +5312,7 @@ mod test {
                 history: vec![],
                 key: None,
                 master_password_reprompt: rbw::api::CipherRepromptType::None,
+                revision_date: None,
             },
             DecryptedSearchCipher {
                 id: id.to_string(),
@@ -4171,6 +5327,7 @@ mod test {
                     })
                     .collect(),
                 fields: vec![],
+                field_names: vec![],
                 notes: None,
             },
         )
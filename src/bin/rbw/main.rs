@@ -5,6 +5,8 @@ use clap::{CommandFactory as _, Parser as _};
 
 mod actions;
 mod commands;
+mod pick;
+mod search;
 mod sock;
 
 #[derive(Debug, clap::Args)]
@@ -19,6 +21,36 @@ struct FindArgs {
     ignorecase: bool,
 }
 
+#[derive(Debug, clap::Args)]
+struct CodeArgs {
+    #[arg(
+        short,
+        long,
+        help = "Generate the code from this custom field instead of the \
+            entry's totp secret"
+    )]
+    field: Option<String>,
+    #[cfg(feature = "clipboard")]
+    #[arg(long, help = "Copy result to clipboard")]
+    clipboard: bool,
+    #[arg(
+        short,
+        long,
+        help = "Also display how long the code is valid for (or the \
+            counter, for hotp secrets)"
+    )]
+    verbose: bool,
+    #[arg(long, help = "Display the code for the next time period")]
+    next: bool,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "If the current code is valid for less than this many \
+            seconds, wait for the next one"
+    )]
+    wait: Option<u64>,
+}
+
 #[derive(Debug, clap::Parser)]
 #[command(version, about = "Unofficial Bitwarden CLI")]
 enum Opt {
@@ -45,12 +77,39 @@ enum Opt {
     #[command(about = "Unlock the local Bitwarden database")]
     Unlock,
 
+    #[command(
+        about = "Manage the PIN used to unlock the local database",
+        long_about = "Manage the PIN used to unlock the local database\n\n\
+            Once a PIN is set, it is asked for instead of the master password \
+            when unlocking. After three incorrect attempts, the PIN is \
+            removed and the master password is required again."
+    )]
+    Pin {
+        #[command(subcommand)]
+        pin: Pin,
+    },
+
     #[command(about = "Check if the local Bitwarden database is unlocked")]
     Unlocked,
 
     #[command(about = "Update the local copy of the Bitwarden database")]
     Sync,
 
+    #[command(
+        about = "Manage changes which haven't been sent to the server yet",
+        long_about = "Manage changes which haven't been sent to the server \
+            yet\n\n\
+            Entries which are added, edited or removed while the server \
+            can't be reached are queued in the local database, and sent the \
+            next time the database is synced. Changes to entries which were \
+            also changed on the server in the meantime are not sent, and \
+            stay in the queue until they are discarded."
+    )]
+    Pending {
+        #[command(subcommand)]
+        pending: Pending,
+    },
+
     #[command(
         about = "List all entries in the local Bitwarden database",
         visible_alias = "ls"
@@ -59,7 +118,8 @@ enum Opt {
         #[arg(
             long,
             help = "Fields to display. \
-                Available options are id, name, user, folder, type. \
+                Available options are id, name, user, folder, type, \
+                pending. \
                 Multiple fields will be separated by tabs.",
             default_value = "name",
             use_value_delimiter = true
@@ -86,10 +146,20 @@ enum Opt {
         list_fields: bool,
     },
 
-    #[command(about = "Search for entries")]
+    #[command(
+        about = "Search for entries",
+        long_about = "Search for entries\n\n\
+            Entries are ranked by how well their name matches the search \
+            term (exact matches first, then prefix, substring and fuzzy \
+            matches), followed by entries where only the username, a uri \
+            or a field matched. Words of the form user:<value>, \
+            folder:<value>, uri:<value>, name:<value> or type:<value> \
+            only match that field, and values containing spaces can be \
+            quoted."
+    )]
     Search {
-        #[arg(help = "Search term to locate entries")]
-        term: String,
+        #[arg(help = "Search term to locate entries", required = true)]
+        term: Vec<String>,
         #[arg(
             long,
             help = "Fields to display. \
@@ -101,22 +171,54 @@ enum Opt {
         fields: Vec<String>,
         #[arg(long, help = "Folder name to search in")]
         folder: Option<String>,
+        #[arg(long, help = "Treat the search term as a regular expression")]
+        regex: bool,
+        #[arg(long, help = "Also search custom field names")]
+        deep: bool,
         #[structopt(long, help = "Display output as JSON")]
         raw: bool,
     },
 
     #[command(
-        about = "Display the authenticator code for a given entry",
-        visible_alias = "totp"
+        about = "Interactively choose an entry",
+        long_about = "Interactively choose an entry\n\n\
+            Type to filter the entries (using the same search syntax as \
+            rbw search), and use the arrow keys to select one. Enter \
+            displays the password of the selected entry, ctrl-u its \
+            username and ctrl-t its authenticator code, and ctrl-o opens \
+            its first uri in the browser."
     )]
-    Code {
-        #[command(flatten)]
-        find_args: FindArgs,
+    Pick {
+        #[arg(help = "Initial search term")]
+        term: Vec<String>,
         #[cfg(feature = "clipboard")]
         #[structopt(long, help = "Copy result to clipboard")]
         clipboard: bool,
     },
 
+    #[command(about = "Display the authenticator code for a given entry")]
+    Code {
+        #[command(flatten)]
+        find_args: FindArgs,
+        #[command(flatten)]
+        code_args: CodeArgs,
+    },
+
+    #[command(
+        about = "Display the authenticator code for a given entry, or set \
+            its totp secret",
+        args_conflicts_with_subcommands = true,
+        subcommand_negates_reqs = true
+    )]
+    Totp {
+        #[command(subcommand)]
+        totp: Option<Totp>,
+        #[command(flatten)]
+        find_args: Option<FindArgs>,
+        #[command(flatten)]
+        code_args: CodeArgs,
+    },
+
     #[command(
         about = "Add a new password to the database",
         long_about = "Add a new password to the database\n\n\
@@ -223,6 +325,28 @@ enum Opt {
         find_args: FindArgs,
     },
 
+    #[command(about = "Manage ssh keys")]
+    SshKey {
+        #[command(subcommand)]
+        ssh_key: SshKey,
+    },
+
+    #[command(
+        about = "View the agent audit log",
+        long_about = "View the agent audit log\n\n\
+            When the audit_log configuration option is enabled, the agent \
+            records every request to decrypt or encrypt data, copy data to \
+            the clipboard, or sign data with an ssh key. Each line contains \
+            the timestamp, action, outcome, entry id (if known) and the \
+            client process which made the request."
+    )]
+    Log {
+        #[arg(short = 'n', long, help = "Only show the last N records")]
+        lines: Option<usize>,
+        #[structopt(long, help = "Display output as JSON")]
+        raw: bool,
+    },
+
     #[command(about = "Lock the password database")]
     Lock,
 
@@ -248,17 +372,30 @@ impl Opt {
             Self::Register => "register".to_string(),
             Self::Login => "login".to_string(),
             Self::Unlock => "unlock".to_string(),
+            Self::Pin { pin } => format!("pin {}", pin.subcommand_name()),
             Self::Unlocked => "unlocked".to_string(),
             Self::Sync => "sync".to_string(),
+            Self::Pending { pending } => {
+                format!("pending {}", pending.subcommand_name())
+            }
             Self::List { .. } => "list".to_string(),
             Self::Get { .. } => "get".to_string(),
             Self::Search { .. } => "search".to_string(),
+            Self::Pick { .. } => "pick".to_string(),
             Self::Code { .. } => "code".to_string(),
+            Self::Totp { totp, .. } => totp.as_ref().map_or_else(
+                || "totp".to_string(),
+                |totp| format!("totp {}", totp.subcommand_name()),
+            ),
             Self::Add { .. } => "add".to_string(),
             Self::Generate { .. } => "generate".to_string(),
             Self::Edit { .. } => "edit".to_string(),
             Self::Remove { .. } => "remove".to_string(),
             Self::History { .. } => "history".to_string(),
+            Self::SshKey { ssh_key } => {
+                format!("ssh-key {}", ssh_key.subcommand_name())
+            }
+            Self::Log { .. } => "log".to_string(),
             Self::Lock => "lock".to_string(),
             Self::Purge => "purge".to_string(),
             Self::StopAgent => "stop-agent".to_string(),
@@ -307,6 +444,140 @@ impl Config {
     }
 }
 
+#[derive(Debug, clap::Parser)]
+enum Pin {
+    #[command(about = "Set the PIN")]
+    Set,
+    #[command(about = "Remove the PIN")]
+    Clear,
+}
+
+impl Pin {
+    fn subcommand_name(&self) -> String {
+        match self {
+            Self::Set => "set",
+            Self::Clear => "clear",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+enum Totp {
+    #[command(
+        about = "Add or replace the totp secret for an entry",
+        long_about = "Add or replace the totp secret for an entry\n\n\
+            The secret can be an otpauth:// uri or a base32 secret, and is \
+            read from stdin, or it can be decoded from a PNG or JPEG image \
+            of a QR code given with --image. The current code is displayed \
+            for confirmation before the secret is saved."
+    )]
+    Set {
+        #[command(flatten)]
+        find_args: FindArgs,
+        #[arg(
+            long,
+            value_name = "PATH",
+            help = "Read the secret from a QR code in this image"
+        )]
+        image: Option<std::path::PathBuf>,
+        #[arg(short, long, help = "Don't ask for confirmation")]
+        yes: bool,
+    },
+}
+
+impl Totp {
+    fn subcommand_name(&self) -> String {
+        match self {
+            Self::Set { .. } => "set",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+enum Pending {
+    #[command(about = "List the queued changes")]
+    List,
+    #[command(about = "Discard queued changes")]
+    Discard {
+        #[arg(help = "Name or UUID of the entry (defaults to all entries)")]
+        name: Option<String>,
+    },
+}
+
+impl Pending {
+    fn subcommand_name(&self) -> String {
+        match self {
+            Self::List => "list",
+            Self::Discard { .. } => "discard",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Debug, clap::Parser)]
+enum SshKey {
+    #[command(
+        about = "Generate a new ssh key and save it to the database",
+        long_about = "Generate a new ssh key and save it to the database\n\n\
+            Prints the fingerprint and public key of the generated key."
+    )]
+    Generate {
+        #[arg(help = "Name of the ssh key entry")]
+        name: String,
+        #[arg(
+            long = "type",
+            value_name = "TYPE",
+            value_enum,
+            default_value_t = SshKeyType::Ed25519,
+            help = "Type of key to generate"
+        )]
+        ty: SshKeyType,
+        #[arg(long, help = "Folder for the ssh key entry")]
+        folder: Option<String>,
+    },
+    #[command(about = "Display the public key of an ssh key entry")]
+    Public {
+        #[command(flatten)]
+        find_args: FindArgs,
+    },
+}
+
+impl SshKey {
+    fn subcommand_name(&self) -> String {
+        match self {
+            Self::Generate { .. } => "generate",
+            Self::Public { .. } => "public",
+        }
+        .to_string()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, clap::ValueEnum)]
+enum SshKeyType {
+    Ed25519,
+    #[value(name = "rsa-4096")]
+    Rsa4096,
+    #[value(help = "ECDSA with the NIST P-256 curve")]
+    Ecdsa,
+}
+
+impl SshKeyType {
+    fn algorithm(self) -> ssh_agent_lib::ssh_key::Algorithm {
+        match self {
+            Self::Ed25519 => ssh_agent_lib::ssh_key::Algorithm::Ed25519,
+            // the key size is set when generating the key
+            Self::Rsa4096 => {
+                ssh_agent_lib::ssh_key::Algorithm::Rsa { hash: None }
+            }
+            Self::Ecdsa => ssh_agent_lib::ssh_key::Algorithm::Ecdsa {
+                curve: ssh_agent_lib::ssh_key::EcdsaCurve::NistP256,
+            },
+        }
+    }
+}
+
 fn main() {
     let opt = Opt::parse();
 
@@ -335,8 +606,18 @@ fn main() {
         Opt::Register => commands::register(),
         Opt::Login => commands::login(),
         Opt::Unlock => commands::unlock(),
+        Opt::Pin { pin } => match pin {
+            Pin::Set => commands::pin_set(),
+            Pin::Clear => commands::pin_clear(),
+        },
         Opt::Unlocked => commands::unlocked(),
         Opt::Sync => commands::sync(),
+        Opt::Pending { pending } => match pending {
+            Pending::List => commands::pending_list(),
+            Pending::Discard { name } => {
+                commands::pending_discard(name.as_deref())
+            }
+        },
         Opt::List { fields, raw } => commands::list(&fields, raw),
         Opt::Get {
             find_args,
@@ -364,22 +645,72 @@ fn main() {
             term,
             fields,
             folder,
+            regex,
+            deep,
             raw,
-        } => commands::search(&term, &fields, folder.as_deref(), raw),
-        Opt::Code {
-            find_args,
+        } => commands::search(
+            &term.join(" "),
+            &fields,
+            folder.as_deref(),
+            regex,
+            deep,
+            raw,
+        ),
+        Opt::Pick {
+            term,
+            #[cfg(feature = "clipboard")]
+            clipboard,
+        } => commands::pick(
+            &term.join(" "),
             #[cfg(feature = "clipboard")]
             clipboard,
+            #[cfg(not(feature = "clipboard"))]
+            false,
+        ),
+        Opt::Code {
+            find_args,
+            code_args,
+        }
+        | Opt::Totp {
+            totp: None,
+            find_args: Some(find_args),
+            code_args,
         } => commands::code(
             find_args.needle,
             find_args.user.as_deref(),
             find_args.folder.as_deref(),
             #[cfg(feature = "clipboard")]
-            clipboard,
+            code_args.clipboard,
             #[cfg(not(feature = "clipboard"))]
             false,
             find_args.ignorecase,
+            code_args.field.as_deref(),
+            code_args.verbose,
+            code_args.next,
+            code_args.wait,
+        ),
+        Opt::Totp {
+            totp:
+                Some(Totp::Set {
+                    find_args,
+                    image,
+                    yes,
+                }),
+            ..
+        } => commands::totp_set(
+            find_args.needle,
+            find_args.user.as_deref(),
+            find_args.folder.as_deref(),
+            find_args.ignorecase,
+            image.as_deref(),
+            yes,
         ),
+        // clap requires either the arguments or a subcommand
+        Opt::Totp {
+            totp: None,
+            find_args: None,
+            ..
+        } => unreachable!(),
         Opt::Add {
             name,
             user,
@@ -448,6 +779,22 @@ fn main() {
             find_args.folder.as_deref(),
             find_args.ignorecase,
         ),
+        Opt::SshKey { ssh_key } => match ssh_key {
+            SshKey::Generate { name, ty, folder } => {
+                commands::ssh_key_generate(
+                    &name,
+                    ty.algorithm(),
+                    folder.as_deref(),
+                )
+            }
+            SshKey::Public { find_args } => commands::ssh_key_public(
+                find_args.needle,
+                find_args.user.as_deref(),
+                find_args.folder.as_deref(),
+                find_args.ignorecase,
+            ),
+        },
+        Opt::Log { lines, raw } => commands::log(lines, raw),
         Opt::Lock => commands::lock(),
         Opt::Purge => commands::purge(),
         Opt::StopAgent => commands::stop_agent(),
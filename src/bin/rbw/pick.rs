@@ -0,0 +1,376 @@
+// the interactive picker for rbw pick. entries are filtered as the query is
+// typed (using the same query syntax and ranking as rbw search), and the
+// metadata of the selected entry is shown alongside the list. secret values
+// are never displayed here - the caller decrypts them once an entry and an
+// action have been chosen, by id, so entries with duplicate names are never
+// confused with each other.
+
+use crossterm::event::{KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+const HELP: &str =
+    "enter: password  ^u: username  ^t: totp  ^o: open uri  esc: quit";
+
+// below this width, the preview is hidden so that the list stays readable
+const MIN_PREVIEW_WIDTH: u16 = 60;
+
+pub struct Item {
+    pub id: String,
+    pub name: String,
+    pub user: Option<String>,
+    pub folder: Option<String>,
+    pub entry_type: String,
+    pub uris: Vec<String>,
+}
+
+impl Item {
+    fn searchable(&self) -> crate::search::Searchable<'_> {
+        crate::search::Searchable {
+            name: &self.name,
+            user: self.user.as_deref(),
+            folder: self.folder.as_deref(),
+            entry_type: &self.entry_type,
+            uris: self.uris.iter().map(String::as_str).collect(),
+            field_names: vec![],
+            field_values: vec![],
+            notes: None,
+        }
+    }
+
+    fn display_name(&self) -> String {
+        let name = self.folder.as_ref().map_or_else(
+            || self.name.clone(),
+            |folder| format!("{folder}/{}", self.name),
+        );
+        match &self.user {
+            Some(user) => format!("{name} ({user})"),
+            None => name,
+        }
+    }
+
+    fn preview(&self) -> Vec<String> {
+        let mut lines = vec![format!("Name: {}", self.name)];
+        if let Some(user) = &self.user {
+            lines.push(format!("Username: {user}"));
+        }
+        if let Some(folder) = &self.folder {
+            lines.push(format!("Folder: {folder}"));
+        }
+        lines.push(format!("Type: {}", self.entry_type));
+        for uri in &self.uris {
+            lines.push(format!("URI: {uri}"));
+        }
+        lines.push(format!("ID: {}", self.id));
+        lines
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Password,
+    Username,
+    Totp,
+    OpenUri,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Outcome {
+    Quit,
+    Select(Action),
+}
+
+struct Picker {
+    items: Vec<Item>,
+    query: String,
+    // indexes into items, in ranked order
+    matches: Vec<usize>,
+    selected: usize,
+    offset: usize,
+}
+
+impl Picker {
+    fn new(items: Vec<Item>, query: &str) -> Self {
+        let mut picker = Self {
+            items,
+            query: query.to_string(),
+            matches: vec![],
+            selected: 0,
+            offset: 0,
+        };
+        picker.filter();
+        picker
+    }
+
+    fn filter(&mut self) {
+        // queries can only fail to parse in regex mode
+        let query =
+            crate::search::Query::parse(&self.query, false, false).unwrap();
+        let mut matches: Vec<_> = self
+            .items
+            .iter()
+            .enumerate()
+            .filter_map(|(i, item)| {
+                query.rank(&item.searchable()).map(|rank| (rank, i))
+            })
+            .collect();
+        // stable, so that entries with the same name stay in a consistent
+        // order
+        matches.sort_by(|(a_rank, a), (b_rank, b)| {
+            a_rank
+                .cmp(b_rank)
+                .then_with(|| self.items[*a].name.cmp(&self.items[*b].name))
+        });
+        self.matches = matches.into_iter().map(|(_, i)| i).collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+
+    fn selected_item(&self) -> Option<&Item> {
+        self.matches.get(self.selected).map(|i| &self.items[*i])
+    }
+
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Outcome> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Esc => return Some(Outcome::Quit),
+            KeyCode::Char('c' | 'd' | 'g') if ctrl => {
+                return Some(Outcome::Quit);
+            }
+            KeyCode::Enter => return self.select(Action::Password),
+            KeyCode::Char('u') if ctrl => {
+                return self.select(Action::Username);
+            }
+            KeyCode::Char('t') if ctrl => return self.select(Action::Totp),
+            KeyCode::Char('o') if ctrl => {
+                return self.select(Action::OpenUri);
+            }
+            KeyCode::Up | KeyCode::BackTab => self.move_selection(-1),
+            KeyCode::Char('p' | 'k') if ctrl => self.move_selection(-1),
+            KeyCode::Down | KeyCode::Tab => self.move_selection(1),
+            KeyCode::Char('n' | 'j') if ctrl => self.move_selection(1),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.filter();
+            }
+            KeyCode::Char('w') if ctrl => {
+                let trimmed = self.query.trim_end();
+                let len =
+                    trimmed.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                self.query.truncate(len);
+                self.filter();
+            }
+            KeyCode::Char(c) if !ctrl => {
+                self.query.push(c);
+                self.filter();
+            }
+            _ => {}
+        }
+        None
+    }
+
+    fn select(&self, action: Action) -> Option<Outcome> {
+        self.selected_item().map(|_| Outcome::Select(action))
+    }
+
+    fn move_selection(&mut self, by: isize) {
+        if self.matches.is_empty() {
+            return;
+        }
+        self.selected = self
+            .selected
+            .saturating_add_signed(by)
+            .min(self.matches.len() - 1);
+    }
+
+    fn draw(&mut self, out: &mut impl std::io::Write) -> anyhow::Result<()> {
+        use crossterm::{cursor, style, terminal};
+
+        let (cols, rows) = terminal::size()?;
+        let list_rows = usize::from(rows.saturating_sub(3));
+        let (list_width, preview_width) = if cols >= MIN_PREVIEW_WIDTH {
+            (cols / 2, cols - cols / 2 - 2)
+        } else {
+            (cols, 0)
+        };
+        let list_width = usize::from(list_width);
+        let preview_width = usize::from(preview_width);
+
+        // keep the selected entry on screen
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if list_rows > 0 && self.selected >= self.offset + list_rows {
+            self.offset = self.selected + 1 - list_rows;
+        }
+
+        crossterm::queue!(
+            out,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::MoveTo(0, 1),
+            style::SetAttribute(style::Attribute::Dim),
+            style::Print(truncate(
+                &format!("  {}/{}", self.matches.len(), self.items.len()),
+                usize::from(cols),
+            )),
+            cursor::MoveTo(0, rows.saturating_sub(1)),
+            style::Print(truncate(HELP, usize::from(cols))),
+            style::SetAttribute(style::Attribute::Reset),
+        )?;
+
+        let preview = self.selected_item().map(Item::preview);
+        for row in 0..list_rows {
+            // unwrap is safe because row is less than the terminal height
+            let y = u16::try_from(row + 2).unwrap();
+            if let Some(i) = self.matches.get(self.offset + row) {
+                let line = truncate(
+                    &format!("  {}", self.items[*i].display_name()),
+                    list_width,
+                );
+                crossterm::queue!(out, cursor::MoveTo(0, y))?;
+                if self.offset + row == self.selected {
+                    crossterm::queue!(
+                        out,
+                        style::SetAttribute(style::Attribute::Reverse),
+                        style::Print(format!("{line:list_width$}")),
+                        style::SetAttribute(style::Attribute::Reset),
+                    )?;
+                } else {
+                    crossterm::queue!(out, style::Print(line))?;
+                }
+            }
+            if preview_width > 0 {
+                // unwrap is safe because list_width is derived from cols
+                let x = u16::try_from(list_width).unwrap();
+                crossterm::queue!(out, cursor::MoveTo(x, y))?;
+                let line = preview
+                    .as_ref()
+                    .and_then(|preview| preview.get(row))
+                    .map_or_else(String::new, |line| {
+                        truncate(line, preview_width)
+                    });
+                crossterm::queue!(out, style::Print(format!("│ {line}")))?;
+            }
+        }
+
+        let prompt =
+            truncate(&format!("> {}", self.query), usize::from(cols));
+        // unwrap is safe because the prompt was truncated to the width of
+        // the terminal
+        let x = u16::try_from(prompt.chars().count()).unwrap();
+        crossterm::queue!(
+            out,
+            cursor::MoveTo(0, 0),
+            style::Print(prompt),
+            cursor::MoveTo(x, 0),
+        )?;
+        out.flush()?;
+
+        Ok(())
+    }
+
+    fn event_loop(
+        &mut self,
+        out: &mut impl std::io::Write,
+    ) -> anyhow::Result<Outcome> {
+        loop {
+            self.draw(out)?;
+            if let crossterm::event::Event::Key(key) =
+                crossterm::event::read()?
+            {
+                if key.kind == KeyEventKind::Press {
+                    if let Some(outcome) = self.handle_key(key) {
+                        return Ok(outcome);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// runs the picker on the terminal, returning the id of the chosen entry and
+// what to do with it, or None if the user quit without choosing. the picker
+// is drawn on stderr, so that stdout can still be piped elsewhere.
+pub fn run(
+    items: Vec<Item>,
+    query: &str,
+) -> anyhow::Result<Option<(String, Action)>> {
+    let mut picker = Picker::new(items, query);
+    let mut out = std::io::stderr();
+
+    crossterm::terminal::enable_raw_mode()?;
+    let res =
+        crossterm::execute!(out, crossterm::terminal::EnterAlternateScreen)
+            .map_err(anyhow::Error::from)
+            .and_then(|()| picker.event_loop(&mut out));
+
+    // always restore the terminal, even if drawing failed
+    let restored =
+        crossterm::execute!(out, crossterm::terminal::LeaveAlternateScreen)
+            .and_then(|()| crossterm::terminal::disable_raw_mode());
+    let outcome = res?;
+    restored?;
+
+    Ok(match outcome {
+        Outcome::Select(action) => {
+            picker.selected_item().map(|item| (item.id.clone(), action))
+        }
+        Outcome::Quit => None,
+    })
+}
+
+// entry names come from the server, so control characters are replaced to
+// avoid messing up the terminal
+fn truncate(s: &str, width: usize) -> String {
+    s.chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .take(width)
+        .collect()
+}
+
+#[test]
+fn test_picker() {
+    let item = |id: &str, name: &str, user: Option<&str>| Item {
+        id: id.to_string(),
+        name: name.to_string(),
+        user: user.map(str::to_string),
+        folder: None,
+        entry_type: "Login".to_string(),
+        uris: vec![],
+    };
+    let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+    let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+
+    let mut picker = Picker::new(
+        vec![
+            item("1", "gitlab", Some("alice")),
+            item("2", "github", Some("alice")),
+            item("3", "github", Some("bob")),
+            item("4", "bitwarden", None),
+        ],
+        "",
+    );
+    assert_eq!(picker.matches, vec![3, 1, 2, 0]);
+
+    for c in "github".chars() {
+        assert_eq!(picker.handle_key(key(KeyCode::Char(c))), None);
+    }
+    assert_eq!(picker.matches, vec![1, 2]);
+    picker.handle_key(key(KeyCode::Down));
+    picker.handle_key(key(KeyCode::Down));
+    assert_eq!(picker.selected_item().unwrap().id, "3");
+    assert_eq!(
+        picker.handle_key(ctrl('u')),
+        Some(Outcome::Select(Action::Username))
+    );
+
+    picker.handle_key(ctrl('w'));
+    for c in "user:bob git".chars() {
+        picker.handle_key(key(KeyCode::Char(c)));
+    }
+    assert_eq!(picker.matches, vec![2]);
+
+    for c in "zzz".chars() {
+        picker.handle_key(key(KeyCode::Char(c)));
+    }
+    assert!(picker.matches.is_empty());
+    assert_eq!(picker.handle_key(key(KeyCode::Enter)), None);
+    assert_eq!(picker.handle_key(key(KeyCode::Esc)), Some(Outcome::Quit));
+}
@@ -0,0 +1,351 @@
+// query parsing and ranking for rbw search. a query is a list of words,
+// where words of the form scope:value (like user:alice or folder:infra)
+// filter on a specific field, and the remaining words are matched against
+// the entry as a whole. values containing spaces can be quoted, like
+// folder:"shared logins".
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scope {
+    Name,
+    User,
+    Folder,
+    Uri,
+    Type,
+}
+
+impl Scope {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "name" => Some(Self::Name),
+            "user" => Some(Self::User),
+            "folder" => Some(Self::Folder),
+            "uri" | "url" => Some(Self::Uri),
+            "type" => Some(Self::Type),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Pattern {
+    // stored lowercased, since text matches are always case insensitive
+    Text(String),
+    Regex(regex::Regex),
+}
+
+impl Pattern {
+    fn new(s: &str, regex: bool) -> anyhow::Result<Self> {
+        if regex {
+            Ok(Self::Regex(
+                regex::RegexBuilder::new(s)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| {
+                        anyhow::anyhow!("invalid regex {s:?}: {e}")
+                    })?,
+            ))
+        } else {
+            Ok(Self::Text(s.to_lowercase()))
+        }
+    }
+
+    fn is_match(&self, haystack: &str) -> bool {
+        match self {
+            Self::Text(s) => haystack.to_lowercase().contains(s),
+            Self::Regex(re) => re.is_match(haystack),
+        }
+    }
+}
+
+// how well an entry matched the query, from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rank {
+    Exact,
+    Prefix,
+    Substring,
+    Fuzzy(std::cmp::Reverse<u32>),
+    // matched the username, a uri, a field or the notes, but not the name
+    Other,
+}
+
+// the parts of an entry that can be searched. field names are only searched
+// when asked for, since they tend to match a lot of unrelated entries.
+pub struct Searchable<'a> {
+    pub name: &'a str,
+    pub user: Option<&'a str>,
+    pub folder: Option<&'a str>,
+    pub entry_type: &'a str,
+    pub uris: Vec<&'a str>,
+    pub field_names: Vec<&'a str>,
+    pub field_values: Vec<&'a str>,
+    pub notes: Option<&'a str>,
+}
+
+#[derive(Debug)]
+pub struct Query {
+    text: Option<Pattern>,
+    filters: Vec<(Scope, Pattern)>,
+    deep: bool,
+}
+
+impl Query {
+    pub fn parse(
+        query: &str,
+        regex: bool,
+        deep: bool,
+    ) -> anyhow::Result<Self> {
+        let mut text = vec![];
+        let mut filters = vec![];
+        for word in split_words(query) {
+            let scoped = word.split_once(':').and_then(|(scope, value)| {
+                Scope::parse(&scope.to_lowercase())
+                    .map(|scope| (scope, value))
+            });
+            match scoped {
+                Some((Scope::Type, value)) => {
+                    filters.push((
+                        Scope::Type,
+                        Pattern::Text(normalize_type(value)),
+                    ));
+                }
+                Some((scope, value)) => {
+                    filters.push((scope, Pattern::new(value, regex)?));
+                }
+                None => text.push(word),
+            }
+        }
+        let text = if text.is_empty() {
+            None
+        } else {
+            Some(Pattern::new(&text.join(" "), regex)?)
+        };
+
+        Ok(Self {
+            text,
+            filters,
+            deep,
+        })
+    }
+
+    // returns how well the entry matches, or None if it doesn't match
+    pub fn rank(&self, entry: &Searchable) -> Option<Rank> {
+        for (scope, pattern) in &self.filters {
+            let matched = match scope {
+                Scope::Name => pattern.is_match(entry.name),
+                Scope::User => {
+                    entry.user.is_some_and(|user| pattern.is_match(user))
+                }
+                Scope::Folder => entry
+                    .folder
+                    .is_some_and(|folder| pattern.is_match(folder)),
+                Scope::Uri => {
+                    entry.uris.iter().any(|uri| pattern.is_match(uri))
+                }
+                // types are matched by prefix, so that type:ssh works
+                Scope::Type => {
+                    let Pattern::Text(ty) = pattern else {
+                        unreachable!()
+                    };
+                    normalize_type(entry.entry_type).starts_with(ty)
+                }
+            };
+            if !matched {
+                return None;
+            }
+        }
+
+        let Some(text) = &self.text else {
+            return Some(Rank::Exact);
+        };
+
+        let name_rank = match text {
+            Pattern::Text(s) => {
+                let name = entry.name.to_lowercase();
+                if name == *s {
+                    Some(Rank::Exact)
+                } else if name.starts_with(s) {
+                    Some(Rank::Prefix)
+                } else if name.contains(s) {
+                    Some(Rank::Substring)
+                } else {
+                    fuzzy_rank(&name, s)
+                }
+            }
+            Pattern::Regex(re) => re.find(entry.name).map(|m| {
+                if m.start() == 0 && m.end() == entry.name.len() {
+                    Rank::Exact
+                } else if m.start() == 0 {
+                    Rank::Prefix
+                } else {
+                    Rank::Substring
+                }
+            }),
+        };
+        if name_rank.is_some() {
+            return name_rank;
+        }
+
+        let mut others = entry
+            .user
+            .iter()
+            .chain(&entry.uris)
+            .chain(&entry.field_values)
+            .chain(&entry.notes);
+        if others.any(|s| text.is_match(s)) {
+            return Some(Rank::Other);
+        }
+        if self.deep && entry.field_names.iter().any(|s| text.is_match(s)) {
+            return Some(Rank::Other);
+        }
+
+        None
+    }
+}
+
+// fuzzy matches are only used for longer search terms whose characters
+// mostly appear together or at the start of words, since otherwise short
+// terms would match most of a large vault
+fn fuzzy_rank(name: &str, needle: &str) -> Option<Rank> {
+    let len =
+        u32::try_from(needle.chars().filter(|c| !c.is_whitespace()).count())
+            .unwrap_or(u32::MAX);
+    if len < 3 {
+        return None;
+    }
+    fuzzy_score(name, needle)
+        .filter(|&score| score >= len.saturating_mul(2))
+        .map(|score| Rank::Fuzzy(std::cmp::Reverse(score)))
+}
+
+// a simple subsequence match: every character of the needle has to appear
+// in the haystack, in order. matches score higher when the characters are
+// consecutive or at the start of a word. both arguments are expected to
+// already be lowercased.
+pub fn fuzzy_score(haystack: &str, needle: &str) -> Option<u32> {
+    let haystack: Vec<char> = haystack.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev: Option<usize> = None;
+    for c in needle.chars().filter(|c| !c.is_whitespace()) {
+        let i = pos + haystack[pos..].iter().position(|&h| h == c)?;
+        score += 1;
+        if prev.is_some_and(|prev| prev + 1 == i) {
+            score += 4;
+        }
+        if i == 0 || !haystack[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        prev = Some(i);
+        pos = i + 1;
+    }
+    Some(score)
+}
+
+// splits on whitespace, except inside double quotes
+fn split_words(s: &str) -> Vec<String> {
+    let mut words = vec![];
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in s.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            c => word.push(c),
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+    words
+}
+
+// "SSH Key", "ssh-key" and "sshkey" are all the same type
+fn normalize_type(s: &str) -> String {
+    s.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+fn searchable<'a>(
+    name: &'a str,
+    user: Option<&'a str>,
+    folder: Option<&'a str>,
+) -> Searchable<'a> {
+    Searchable {
+        name,
+        user,
+        folder,
+        entry_type: "Login",
+        uris: vec!["https://github.com/login"],
+        field_names: vec!["recovery email"],
+        field_values: vec![],
+        notes: Some("shared with the infra team"),
+    }
+}
+
+#[test]
+fn test_split_words() {
+    assert_eq!(
+        split_words(r#"  github user:alice folder:"shared logins" "#),
+        vec!["github", "user:alice", "folder:shared logins"]
+    );
+}
+
+#[test]
+fn test_rank() {
+    let rank = |query: &str, entry: &Searchable| {
+        Query::parse(query, false, false).unwrap().rank(entry)
+    };
+    let github = searchable("GitHub", Some("alice"), Some("infra"));
+    assert_eq!(rank("github", &github), Some(Rank::Exact));
+    assert_eq!(rank("git", &github), Some(Rank::Prefix));
+    assert_eq!(rank("hub", &github), Some(Rank::Substring));
+    assert!(matches!(rank("gthb", &github), Some(Rank::Fuzzy(_))));
+    assert_eq!(rank("alice", &github), Some(Rank::Other));
+    assert_eq!(rank("login", &github), Some(Rank::Other));
+    assert_eq!(rank("bitwarden", &github), None);
+
+    assert!(rank("git", &github) < rank("gthb", &github));
+    assert!(fuzzy_score("git hub", "gh") > fuzzy_score("github", "gh"));
+    assert!(
+        fuzzy_score("github", "gith") > fuzzy_score("gitlab hub", "gith")
+    );
+    assert_eq!(fuzzy_score("github", "hg"), None);
+
+    assert_eq!(rank("user:ALICE", &github), Some(Rank::Exact));
+    assert_eq!(rank("git user:bob", &github), None);
+    assert_eq!(
+        rank("folder:infra uri:github.com", &github),
+        Some(Rank::Exact)
+    );
+    assert_eq!(rank("type:login git", &github), Some(Rank::Prefix));
+    assert_eq!(rank("type:card", &github), None);
+    assert_eq!(rank("user:alice", &searchable("x", None, None)), None);
+
+    // scattered or short fuzzy matches are ignored
+    assert_eq!(rank("gb", &github), None);
+    assert_eq!(rank("gtb", &searchable("legit ebay", None, None)), None);
+
+    // field names are only searched when asked for
+    assert_eq!(rank("infra team", &github), Some(Rank::Other));
+    assert_eq!(rank("recovery", &github), None);
+    assert_eq!(
+        Query::parse("recovery", false, true).unwrap().rank(&github),
+        Some(Rank::Other)
+    );
+
+    let regex = |query: &str, entry: &Searchable| {
+        Query::parse(query, true, false).unwrap().rank(entry)
+    };
+    assert_eq!(regex("^git(hub|lab)$", &github), Some(Rank::Exact));
+    assert_eq!(regex("^g.t", &github), Some(Rank::Prefix));
+    assert_eq!(regex("gthb", &github), None);
+    assert_eq!(regex("user:^al", &github), Some(Rank::Exact));
+    assert!(Query::parse("(", true, false).is_err());
+}
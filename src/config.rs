@@ -14,16 +14,128 @@ pub struct Config {
     pub notifications_url: Option<String>,
     #[serde(default = "default_lock_timeout")]
     pub lock_timeout: u64,
+    #[serde(default)]
+    pub unlock_lifetime: u64,
+    #[serde(default)]
+    pub lock_timeout_exempt_actions: Vec<String>,
     #[serde(default = "default_sync_interval")]
     pub sync_interval: u64,
+    #[serde(default = "default_db_backups")]
+    pub db_backups: usize,
     #[serde(default = "default_pinentry")]
     pub pinentry: String,
+    #[serde(default)]
+    pub prompt: PromptBackend,
+    pub askpass: Option<String>,
     pub client_cert_path: Option<std::path::PathBuf>,
+    #[serde(default)]
+    pub client_policy: ClientPolicy,
+    #[serde(default)]
+    pub allowed_clients: Vec<std::path::PathBuf>,
+    #[serde(default)]
+    pub audit_log: bool,
+    #[serde(default)]
+    pub ssh_confirm: bool,
+    #[serde(default)]
+    pub ssh_refuse_rsa_sha1: bool,
+    #[serde(default)]
+    pub ssh_refuse_forwarded: bool,
+    pub ssh_folder: Option<String>,
+    pub ssh_max_identities: Option<usize>,
+    #[serde(default)]
+    pub session_keyring: bool,
+    #[serde(default)]
+    pub lock_on_suspend: bool,
+    #[serde(default)]
+    pub lock_on_session_lock: bool,
+    #[serde(default)]
+    pub lock_on_screensaver: bool,
+    #[serde(default)]
+    pub lock_on_logout: bool,
     // backcompat, no longer generated in new configs
     #[serde(skip_serializing)]
     pub device_id: Option<String>,
 }
 
+// how the agent treats clients which ask it to decrypt data or store it in
+// the clipboard, based on the executable of the connecting process
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientPolicy {
+    // any process running as the current user is allowed
+    #[default]
+    Allow,
+    // only executables listed in allowed_clients are allowed
+    Allowlist,
+    // executables not listed in allowed_clients require confirmation via
+    // pinentry the first time they connect after unlocking
+    Confirm,
+}
+
+impl std::str::FromStr for ClientPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "allow" => Ok(Self::Allow),
+            "allowlist" => Ok(Self::Allowlist),
+            "confirm" => Ok(Self::Confirm),
+            _ => Err(Error::InvalidClientPolicy {
+                policy: s.to_string(),
+            }),
+        }
+    }
+}
+
+// how the agent asks the user for passwords and confirmations
+#[derive(
+    serde::Serialize,
+    serde::Deserialize,
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PromptBackend {
+    // the program configured in pinentry
+    #[default]
+    Pinentry,
+    // directly on the terminal that rbw was run from
+    Tty,
+    // an ssh-askpass style program
+    Askpass,
+    // systemd's password agent protocol
+    Systemd,
+}
+
+impl std::str::FromStr for PromptBackend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pinentry" => Ok(Self::Pinentry),
+            "tty" => Ok(Self::Tty),
+            "askpass" => Ok(Self::Askpass),
+            "systemd" => Ok(Self::Systemd),
+            _ => Err(Error::InvalidPromptBackend {
+                backend: s.to_string(),
+            }),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -34,9 +146,27 @@ impl Default for Config {
             ui_url: None,
             notifications_url: None,
             lock_timeout: default_lock_timeout(),
+            unlock_lifetime: 0,
+            lock_timeout_exempt_actions: vec![],
             sync_interval: default_sync_interval(),
+            db_backups: default_db_backups(),
             pinentry: default_pinentry(),
+            prompt: PromptBackend::default(),
+            askpass: None,
             client_cert_path: None,
+            client_policy: ClientPolicy::default(),
+            allowed_clients: vec![],
+            audit_log: false,
+            ssh_confirm: false,
+            ssh_refuse_rsa_sha1: false,
+            ssh_refuse_forwarded: false,
+            ssh_folder: None,
+            ssh_max_identities: None,
+            session_keyring: false,
+            lock_on_suspend: false,
+            lock_on_session_lock: false,
+            lock_on_screensaver: false,
+            lock_on_logout: false,
             device_id: None,
         }
     }
@@ -46,10 +176,29 @@ pub fn default_lock_timeout() -> u64 {
     3600
 }
 
+// actions which reset the lock timeout, and so can be listed in
+// lock_timeout_exempt_actions
+pub const LOCK_TIMEOUT_ACTIONS: &[&str] = &[
+    "login",
+    "register",
+    "unlock",
+    "set_pin",
+    "decrypt",
+    "encrypt",
+    "clipboard_store",
+    "index",
+    "ssh_identities",
+    "ssh_sign",
+];
+
 pub fn default_sync_interval() -> u64 {
     3600
 }
 
+pub fn default_db_backups() -> usize {
+    3
+}
+
 pub fn default_pinentry() -> String {
     "pinentry".to_string()
 }
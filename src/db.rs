@@ -1,8 +1,9 @@
 use crate::prelude::*;
 
 use std::io::{Read as _, Write as _};
+use std::os::unix::fs::OpenOptionsExt as _;
 
-use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+use tokio::io::AsyncReadExt as _;
 
 #[derive(
     serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
@@ -19,99 +20,134 @@ pub struct Entry {
     pub history: Vec<HistoryEntry>,
     pub key: Option<String>,
     pub master_password_reprompt: crate::api::CipherRepromptType,
+    // the time the entry was last modified on the server, used to detect
+    // conflicting edits
+    pub revision_date: Option<String>,
 }
 
 impl Entry {
     pub fn master_password_reprompt(&self) -> bool {
         self.master_password_reprompt != crate::api::CipherRepromptType::None
     }
-}
 
-#[derive(serde::Serialize, Debug, Clone, Eq, PartialEq)]
-pub struct Uri {
-    pub uri: String,
-    pub match_type: Option<crate::api::UriMatchType>,
-}
-
-// backwards compatibility
-impl<'de> serde::Deserialize<'de> for Uri {
-    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
-    where
-        D: serde::Deserializer<'de>,
-    {
-        struct StringOrUri;
-        impl<'de> serde::de::Visitor<'de> for StringOrUri {
-            type Value = Uri;
-
-            fn expecting(
-                &self,
-                formatter: &mut std::fmt::Formatter,
-            ) -> std::fmt::Result {
-                formatter.write_str("uri")
+    // all of the encrypted values stored in this entry
+    pub fn cipherstrings(&self) -> Vec<&str> {
+        let mut cipherstrings = vec![self.name.as_str()];
+        match &self.data {
+            EntryData::Login {
+                username,
+                password,
+                totp,
+                uris,
+            } => {
+                cipherstrings.extend(
+                    [username, password, totp]
+                        .into_iter()
+                        .filter_map(Option::as_deref),
+                );
+                cipherstrings.extend(uris.iter().map(|uri| uri.uri.as_str()));
             }
-
-            fn visit_str<E>(
-                self,
-                value: &str,
-            ) -> std::result::Result<Self::Value, E>
-            where
-                E: serde::de::Error,
-            {
-                Ok(Uri {
-                    uri: value.to_string(),
-                    match_type: None,
-                })
+            EntryData::Card {
+                cardholder_name,
+                number,
+                brand,
+                exp_month,
+                exp_year,
+                code,
+            } => {
+                cipherstrings.extend(
+                    [
+                        cardholder_name,
+                        number,
+                        brand,
+                        exp_month,
+                        exp_year,
+                        code,
+                    ]
+                    .into_iter()
+                    .filter_map(Option::as_deref),
+                );
             }
-
-            fn visit_map<M>(
-                self,
-                mut map: M,
-            ) -> std::result::Result<Self::Value, M::Error>
-            where
-                M: serde::de::MapAccess<'de>,
-            {
-                let mut uri = None;
-                let mut match_type = None;
-                while let Some(key) = map.next_key()? {
-                    match key {
-                        "uri" => {
-                            if uri.is_some() {
-                                return Err(
-                                    serde::de::Error::duplicate_field("uri"),
-                                );
-                            }
-                            uri = Some(map.next_value()?);
-                        }
-                        "match_type" => {
-                            if match_type.is_some() {
-                                return Err(
-                                    serde::de::Error::duplicate_field(
-                                        "match_type",
-                                    ),
-                                );
-                            }
-                            match_type = map.next_value()?;
-                        }
-                        _ => {
-                            return Err(serde::de::Error::unknown_field(
-                                key,
-                                &["uri", "match_type"],
-                            ))
-                        }
-                    }
-                }
-
-                uri.map_or_else(
-                    || Err(serde::de::Error::missing_field("uri")),
-                    |uri| Ok(Self::Value { uri, match_type }),
-                )
+            EntryData::Identity {
+                title,
+                first_name,
+                middle_name,
+                last_name,
+                address1,
+                address2,
+                address3,
+                city,
+                state,
+                postal_code,
+                country,
+                phone,
+                email,
+                ssn,
+                license_number,
+                passport_number,
+                username,
+            } => {
+                cipherstrings.extend(
+                    [
+                        title,
+                        first_name,
+                        middle_name,
+                        last_name,
+                        address1,
+                        address2,
+                        address3,
+                        city,
+                        state,
+                        postal_code,
+                        country,
+                        phone,
+                        email,
+                        ssn,
+                        license_number,
+                        passport_number,
+                        username,
+                    ]
+                    .into_iter()
+                    .filter_map(Option::as_deref),
+                );
+            }
+            EntryData::SecureNote => {}
+            EntryData::SshKey {
+                private_key,
+                public_key,
+                fingerprint,
+            } => {
+                cipherstrings.extend(
+                    [private_key, public_key, fingerprint]
+                        .into_iter()
+                        .filter_map(Option::as_deref),
+                );
             }
         }
-
-        deserializer.deserialize_any(StringOrUri)
+        for field in &self.fields {
+            cipherstrings.extend(
+                [&field.name, &field.value]
+                    .into_iter()
+                    .filter_map(Option::as_deref),
+            );
+        }
+        cipherstrings.extend(self.notes.as_deref());
+        cipherstrings.extend(
+            self.history.iter().map(|history| history.password.as_str()),
+        );
+        cipherstrings.retain(|s| !s.is_empty());
+        cipherstrings
     }
 }
 
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
+)]
+pub struct Uri {
+    pub uri: String,
+    pub match_type: Option<crate::api::UriMatchType>,
+}
+
 #[derive(
     serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
 )]
@@ -157,6 +193,19 @@ pub enum EntryData {
     },
 }
 
+impl EntryData {
+    // the name of the entry type, as displayed to the user
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::Login { .. } => "Login",
+            Self::Identity { .. } => "Identity",
+            Self::SshKey { .. } => "SSH Key",
+            Self::SecureNote => "Note",
+            Self::Card { .. } => "Card",
+        }
+    }
+}
+
 #[derive(
     serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
 )]
@@ -175,6 +224,57 @@ pub struct HistoryEntry {
     pub password: String,
 }
 
+// a change which couldn't be sent to the server because it was
+// unreachable, to be sent on the next sync. the entries are stored
+// encrypted, the same as the synced entries are.
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
+)]
+pub struct PendingChange {
+    pub action: PendingAction,
+    // set when the entry was also changed on the server since this change
+    // was made, in which case it won't be sent
+    pub conflict: bool,
+}
+
+#[derive(
+    serde::Serialize, serde::Deserialize, Debug, Clone, Eq, PartialEq,
+)]
+pub enum PendingAction {
+    // the entry has a randomly generated id until the server assigns it a
+    // real one
+    Add { entry: Entry },
+    // orig is the entry as it was last synced from the server, used to
+    // detect conflicting changes
+    Edit { entry: Entry, orig: Entry },
+    Remove { orig: Entry },
+}
+
+impl PendingAction {
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Add { entry } | Self::Edit { entry, .. } => &entry.id,
+            Self::Remove { orig } => &orig.id,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Add { .. } => "add",
+            Self::Edit { .. } => "edit",
+            Self::Remove { .. } => "remove",
+        }
+    }
+
+    // the entry as it will be once this change is applied
+    pub fn entry(&self) -> &Entry {
+        match self {
+            Self::Add { entry } | Self::Edit { entry, .. } => entry,
+            Self::Remove { orig } => orig,
+        }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Default, Debug)]
 pub struct Db {
     pub access_token: Option<String>,
@@ -189,6 +289,17 @@ pub struct Db {
     pub protected_org_keys: std::collections::HashMap<String, String>,
 
     pub entries: Vec<Entry>,
+
+    pub pending: Vec<PendingChange>,
+}
+
+// an exclusive lock on the db, which is released when dropped. both the cli
+// and the agent modify the db by loading it, changing it and saving it
+// again, so this should be held for that whole time (not just while
+// saving), otherwise one of them could overwrite the changes that the other
+// made in the meantime.
+pub struct Lock {
+    _fh: std::fs::File,
 }
 
 impl Db {
@@ -196,6 +307,28 @@ impl Db {
         Self::default()
     }
 
+    pub fn lock(server: &str, email: &str) -> Result<Lock> {
+        lock(&crate::dirs::db_file(server, email))
+    }
+
+    // like lock, but gives up if someone else is still holding the lock
+    // once the timeout has passed, rather than waiting forever
+    pub fn lock_timeout(
+        server: &str,
+        email: &str,
+        timeout: std::time::Duration,
+    ) -> Result<Lock> {
+        lock_timeout(&crate::dirs::db_file(server, email), timeout)
+    }
+
+    pub async fn lock_async(server: &str, email: &str) -> Result<Lock> {
+        let file = crate::dirs::db_file(server, email);
+        // flock is a blocking operation
+        tokio::task::spawn_blocking(move || lock(&file))
+            .await
+            .unwrap()
+    }
+
     pub fn load(server: &str, email: &str) -> Result<Self> {
         let file = crate::dirs::db_file(server, email);
         let mut fh =
@@ -209,9 +342,7 @@ impl Db {
                 source,
                 file: file.clone(),
             })?;
-        let slf: Self = serde_json::from_str(&json)
-            .map_err(|source| Error::LoadDbJson { source, file })?;
-        Ok(slf)
+        Self::from_json(&json, file)
     }
 
     pub async fn load_async(server: &str, email: &str) -> Result<Self> {
@@ -230,80 +361,162 @@ impl Db {
                 file: file.clone(),
             }
         })?;
-        let slf: Self = serde_json::from_str(&json)
-            .map_err(|source| Error::LoadDbJson { source, file })?;
-        Ok(slf)
+        Self::from_json(&json, file)
     }
 
-    // XXX need to make this atomic
-    pub fn save(&self, server: &str, email: &str) -> Result<()> {
+    pub fn save(
+        &self,
+        server: &str,
+        email: &str,
+        backups: usize,
+        _lock: &Lock,
+    ) -> Result<()> {
         let file = crate::dirs::db_file(server, email);
-        // unwrap is safe here because Self::filename is explicitly
-        // constructed as a filename in a directory
-        std::fs::create_dir_all(file.parent().unwrap()).map_err(
-            |source| Error::SaveDb {
-                source,
-                file: file.clone(),
-            },
-        )?;
-        let mut fh =
-            std::fs::File::create(&file).map_err(|source| Error::SaveDb {
-                source,
-                file: file.clone(),
-            })?;
-        fh.write_all(
-            serde_json::to_string(self)
-                .map_err(|source| Error::SaveDbJson {
-                    source,
-                    file: file.clone(),
-                })?
-                .as_bytes(),
-        )
-        .map_err(|source| Error::SaveDb { source, file })?;
-        Ok(())
+        let json = self.to_json().map_err(|source| Error::SaveDbJson {
+            source,
+            file: file.clone(),
+        })?;
+        write_atomic(&file, json.as_bytes(), backups)
     }
 
-    // XXX need to make this atomic
-    pub async fn save_async(&self, server: &str, email: &str) -> Result<()> {
+    pub async fn save_async(
+        &self,
+        server: &str,
+        email: &str,
+        backups: usize,
+        _lock: &Lock,
+    ) -> Result<()> {
         let file = crate::dirs::db_file(server, email);
-        // unwrap is safe here because Self::filename is explicitly
-        // constructed as a filename in a directory
-        tokio::fs::create_dir_all(file.parent().unwrap())
-            .await
-            .map_err(|source| Error::SaveDbAsync {
-                source,
-                file: file.clone(),
-            })?;
-        let mut fh =
-            tokio::fs::File::create(&file).await.map_err(|source| {
-                Error::SaveDbAsync {
+        let json = self.to_json().map_err(|source| Error::SaveDbJson {
+            source,
+            file: file.clone(),
+        })?;
+        // fsync is a blocking operation
+        tokio::task::spawn_blocking(move || {
+            write_atomic(&file, json.as_bytes(), backups)
+        })
+        .await
+        .unwrap()
+    }
+
+    pub fn remove(server: &str, email: &str) -> Result<()> {
+        let file = crate::dirs::db_file(server, email);
+        remove_file(&file)?;
+        // the backups contain the same data, so they need to go too
+        for n in 1.. {
+            let backup = backup_file(&file, n);
+            if !remove_file(&backup)? {
+                break;
+            }
+        }
+        remove_file(&lock_file(&file))?;
+        Ok(())
+    }
+
+    fn from_json(json: &str, file: std::path::PathBuf) -> Result<Self> {
+        let mut db: serde_json::Value =
+            serde_json::from_str(json).map_err(|source| {
+                Error::LoadDbJson {
                     source,
                     file: file.clone(),
                 }
             })?;
-        fh.write_all(
-            serde_json::to_string(self)
-                .map_err(|source| Error::SaveDbJson {
-                    source,
+        let version = match db.get("version") {
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| usize::try_from(version).ok())
+                .filter(|version| *version <= VERSION)
+                .ok_or_else(|| Error::LoadDbVersion {
+                    version: version.to_string(),
                     file: file.clone(),
-                })?
-                .as_bytes(),
-        )
-        .await
-        .map_err(|source| Error::SaveDbAsync { source, file })?;
-        Ok(())
+                })?,
+            None => 0,
+        };
+        for migration in &MIGRATIONS[version..] {
+            migration(&mut db);
+        }
+        serde_json::from_value(db)
+            .map_err(|source| Error::LoadDbJson { source, file })
     }
 
-    pub fn remove(server: &str, email: &str) -> Result<()> {
-        let file = crate::dirs::db_file(server, email);
-        let res = std::fs::remove_file(&file);
-        if let Err(e) = &res {
-            if e.kind() == std::io::ErrorKind::NotFound {
-                return Ok(());
+    fn to_json(&self) -> serde_json::Result<String> {
+        let mut db = serde_json::to_value(self)?;
+        // unwrap is safe because structs are always serialized as objects
+        db.as_object_mut()
+            .unwrap()
+            .insert("version".to_string(), VERSION.into());
+        serde_json::to_string(&db)
+    }
+
+    pub fn pending(&self, id: &str) -> Option<&PendingChange> {
+        self.pending.iter().find(|change| change.action.id() == id)
+    }
+
+    // adds a change to the queue, combining it with any change to the same
+    // entry which is already queued
+    pub fn queue(&mut self, action: PendingAction) {
+        let Some(idx) = self
+            .pending
+            .iter()
+            .position(|change| change.action.id() == action.id())
+        else {
+            self.pending.push(PendingChange {
+                action,
+                conflict: false,
+            });
+            return;
+        };
+
+        let action = match (self.pending.remove(idx).action, action) {
+            // the server never saw this entry, so there's nothing to remove
+            (PendingAction::Add { .. }, PendingAction::Remove { .. }) => {
+                return;
+            }
+            (
+                PendingAction::Add { .. },
+                PendingAction::Edit { entry, .. },
+            ) => PendingAction::Add { entry },
+            (
+                PendingAction::Edit { orig, .. },
+                PendingAction::Edit { entry, .. },
+            ) => PendingAction::Edit { entry, orig },
+            (
+                PendingAction::Edit { orig, .. },
+                PendingAction::Remove { .. },
+            ) => PendingAction::Remove { orig },
+            (_, action) => action,
+        };
+        self.pending.insert(
+            idx,
+            PendingChange {
+                action,
+                conflict: false,
+            },
+        );
+    }
+
+    // updates the entries to reflect the changes which haven't been sent to
+    // the server yet. the result should only be used for display, and not
+    // saved.
+    pub fn apply_pending(&mut self) {
+        for change in &self.pending {
+            let id = change.action.id();
+            match &change.action {
+                PendingAction::Add { entry } => {
+                    self.entries.push(entry.clone());
+                }
+                PendingAction::Edit { entry, .. } => {
+                    if let Some(existing) =
+                        self.entries.iter_mut().find(|e| e.id == id)
+                    {
+                        *existing = entry.clone();
+                    }
+                }
+                PendingAction::Remove { .. } => {
+                    self.entries.retain(|e| e.id != id);
+                }
             }
         }
-        res.map_err(|source| Error::RemoveDb { source, file })?;
-        Ok(())
     }
 
     pub fn needs_login(&self) -> bool {
@@ -314,3 +527,479 @@ impl Db {
             || self.protected_key.is_none()
     }
 }
+
+// MIGRATIONS[n] upgrades the json representation of a db from version n to
+// version n + 1, and VERSION is the version that is written when saving.
+// files from before the db format was versioned are treated as version 0.
+// to change the format in a way that can't be read by the previous version
+// of the Db struct, add a new migration to the end of this list rather than
+// adding serde fallbacks.
+const MIGRATIONS: &[fn(&mut serde_json::Value)] =
+    &[migrate_unversioned, migrate_pending, migrate_revision_dates];
+const VERSION: usize = MIGRATIONS.len();
+
+// uris used to be stored as plain strings, before match types were
+// supported
+fn migrate_unversioned(db: &mut serde_json::Value) {
+    let entries = db
+        .get_mut("entries")
+        .and_then(serde_json::Value::as_array_mut);
+    for entry in entries.into_iter().flatten() {
+        let uris = entry
+            .pointer_mut("/data/Login/uris")
+            .and_then(serde_json::Value::as_array_mut);
+        for uri in uris.into_iter().flatten() {
+            if let Some(s) = uri.as_str() {
+                *uri = serde_json::json!({ "uri": s, "match_type": null });
+            }
+        }
+    }
+}
+
+// added the queue of changes made while offline
+fn migrate_pending(db: &mut serde_json::Value) {
+    if let Some(db) = db.as_object_mut() {
+        db.insert("pending".to_string(), serde_json::json!([]));
+    }
+}
+
+// added the revision date of each entry. this is only known once the db
+// has been synced again.
+fn migrate_revision_dates(db: &mut serde_json::Value) {
+    let mut entries = vec![];
+    for (key, value) in db.as_object_mut().into_iter().flatten() {
+        let Some(values) = value.as_array_mut() else {
+            continue;
+        };
+        match key.as_str() {
+            "entries" => entries.extend(values.iter_mut()),
+            // queued changes look like
+            // {"action": {"Edit": {"entry": {...}, "orig": {...}}}, ...}
+            "pending" => entries.extend(
+                values
+                    .iter_mut()
+                    .filter_map(|change| change.get_mut("action"))
+                    .filter_map(serde_json::Value::as_object_mut)
+                    .flat_map(|action| action.values_mut())
+                    .filter_map(serde_json::Value::as_object_mut)
+                    .flat_map(|action| action.values_mut()),
+            ),
+            _ => {}
+        }
+    }
+    for entry in entries {
+        if let Some(entry) = entry.as_object_mut() {
+            entry
+                .insert("revision_date".to_string(), serde_json::Value::Null);
+        }
+    }
+}
+
+// replaces the db file by writing to a temporary file in the same directory
+// and renaming it into place, so that a crash in the middle of writing can't
+// leave a truncated db behind. the previous contents are kept around as
+// file.1 through file.N (with file.1 being the most recent), in case the
+// new contents turn out to be bad. the caller must hold the lock (see Lock).
+fn write_atomic(
+    file: &std::path::Path,
+    data: &[u8],
+    backups: usize,
+) -> Result<()> {
+    let err = |source| Error::SaveDb {
+        source,
+        file: file.to_path_buf(),
+    };
+
+    // unwrap is safe here because db_file is explicitly constructed as a
+    // filename in a directory
+    let dir = file.parent().unwrap();
+    std::fs::create_dir_all(dir).map_err(err)?;
+
+    let mut tmp = tempfile::NamedTempFile::new_in(dir).map_err(err)?;
+    tmp.write_all(data).map_err(err)?;
+    tmp.as_file().sync_all().map_err(err)?;
+
+    rotate_backups(file, backups).map_err(err)?;
+    tmp.persist(file).map_err(|e| err(e.error))?;
+    // make sure the rename itself is durable
+    std::fs::File::open(dir)
+        .and_then(|dir| dir.sync_all())
+        .map_err(err)?;
+
+    Ok(())
+}
+
+// this locks a separate file rather than the db itself, since the db file is
+// replaced on every write
+fn lock(file: &std::path::Path) -> Result<Lock> {
+    let (fh, file) = open_lock_file(file)?;
+    rustix::fs::flock(&fh, rustix::fs::FlockOperation::LockExclusive)
+        .map_err(|e| Error::LockDb {
+            source: e.into(),
+            file,
+        })?;
+    Ok(Lock { _fh: fh })
+}
+
+fn lock_timeout(
+    file: &std::path::Path,
+    timeout: std::time::Duration,
+) -> Result<Lock> {
+    let (fh, file) = open_lock_file(file)?;
+    let start = std::time::Instant::now();
+    loop {
+        match rustix::fs::flock(
+            &fh,
+            rustix::fs::FlockOperation::NonBlockingLockExclusive,
+        ) {
+            Ok(()) => return Ok(Lock { _fh: fh }),
+            Err(rustix::io::Errno::WOULDBLOCK)
+                if start.elapsed() < timeout =>
+            {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(rustix::io::Errno::WOULDBLOCK) => {
+                return Err(Error::LockDbTimeout { file });
+            }
+            Err(e) => {
+                return Err(Error::LockDb {
+                    source: e.into(),
+                    file,
+                });
+            }
+        }
+    }
+}
+
+fn open_lock_file(
+    file: &std::path::Path,
+) -> Result<(std::fs::File, std::path::PathBuf)> {
+    let file = lock_file(file);
+    // unwrap is safe here because db_file is explicitly constructed as a
+    // filename in a directory
+    std::fs::create_dir_all(file.parent().unwrap()).map_err(|source| {
+        Error::LockDb {
+            source,
+            file: file.clone(),
+        }
+    })?;
+    let fh = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(&file)
+        .map_err(|source| Error::LockDb {
+            source,
+            file: file.clone(),
+        })?;
+    Ok((fh, file))
+}
+
+fn rotate_backups(
+    file: &std::path::Path,
+    backups: usize,
+) -> std::io::Result<()> {
+    if backups == 0 || !file.try_exists()? {
+        return Ok(());
+    }
+
+    for n in (1..backups).rev() {
+        match std::fs::rename(backup_file(file, n), backup_file(file, n + 1))
+        {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            res => res?,
+        }
+    }
+
+    // the db file is about to be replaced rather than modified, so a hard
+    // link is enough to preserve its current contents
+    let backup = backup_file(file, 1);
+    match std::fs::remove_file(&backup) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        res => res?,
+    }
+    std::fs::hard_link(file, backup)
+}
+
+fn backup_file(file: &std::path::Path, n: usize) -> std::path::PathBuf {
+    let mut backup = file.as_os_str().to_os_string();
+    backup.push(format!(".{n}"));
+    backup.into()
+}
+
+fn lock_file(file: &std::path::Path) -> std::path::PathBuf {
+    let mut lock = file.as_os_str().to_os_string();
+    lock.push(".lock");
+    lock.into()
+}
+
+// returns whether the file existed
+fn remove_file(file: &std::path::Path) -> Result<bool> {
+    match std::fs::remove_file(file) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(source) => Err(Error::RemoveDb {
+            source,
+            file: file.to_path_buf(),
+        }),
+    }
+}
+
+#[test]
+fn test_write_atomic() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("db.json");
+
+    for i in 0..5 {
+        write_atomic(&file, format!("{i}").as_bytes(), 3).unwrap();
+    }
+    let read = |path| std::fs::read_to_string(path).unwrap();
+    assert_eq!(read(file.clone()), "4");
+    assert_eq!(read(backup_file(&file, 1)), "3");
+    assert_eq!(read(backup_file(&file, 2)), "2");
+    assert_eq!(read(backup_file(&file, 3)), "1");
+    assert!(!backup_file(&file, 4).exists());
+
+    write_atomic(&file, b"5", 0).unwrap();
+    assert_eq!(read(file.clone()), "5");
+    assert_eq!(read(backup_file(&file, 1)), "3");
+}
+
+#[test]
+fn test_lock() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("db.json");
+
+    let held = lock(&file).unwrap();
+    assert!(matches!(
+        lock_timeout(&file, std::time::Duration::from_millis(200)),
+        Err(Error::LockDbTimeout { .. })
+    ));
+    let fh = std::fs::File::open(lock_file(&file)).unwrap();
+    assert!(rustix::fs::flock(
+        &fh,
+        rustix::fs::FlockOperation::NonBlockingLockExclusive
+    )
+    .is_err());
+    drop(held);
+    rustix::fs::flock(
+        &fh,
+        rustix::fs::FlockOperation::NonBlockingLockExclusive,
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+fn test_db(version: Option<u64>, uris: &serde_json::Value) -> String {
+    let mut db = serde_json::json!({
+        "access_token": null,
+        "refresh_token": null,
+        "kdf": null,
+        "iterations": null,
+        "memory": null,
+        "parallelism": null,
+        "protected_key": null,
+        "protected_private_key": null,
+        "protected_org_keys": {},
+        "entries": [{
+            "id": "id",
+            "org_id": null,
+            "folder": null,
+            "folder_id": null,
+            "name": "name",
+            "data": {
+                "Login": {
+                    "username": null,
+                    "password": null,
+                    "totp": null,
+                    "uris": uris,
+                },
+            },
+            "fields": [],
+            "notes": null,
+            "history": [],
+            "key": null,
+            "master_password_reprompt": 0,
+        }],
+    });
+    if let Some(version) = version {
+        db["version"] = version.into();
+    }
+    db.to_string()
+}
+
+#[cfg(test)]
+fn test_uris(db: &Db) -> &[Uri] {
+    let EntryData::Login { uris, .. } = &db.entries[0].data else {
+        panic!("not a login entry");
+    };
+    uris
+}
+
+#[test]
+fn test_migrate_unversioned() {
+    let file = std::path::PathBuf::from("db.json");
+
+    // uris as plain strings
+    let db = Db::from_json(
+        &test_db(None, &serde_json::json!(["https://a.com", "b.com"])),
+        file.clone(),
+    )
+    .unwrap();
+    assert_eq!(
+        test_uris(&db),
+        &[
+            Uri {
+                uri: "https://a.com".to_string(),
+                match_type: None,
+            },
+            Uri {
+                uri: "b.com".to_string(),
+                match_type: None,
+            },
+        ]
+    );
+
+    // uris with match types
+    let db = Db::from_json(
+        &test_db(
+            None,
+            &serde_json::json!([
+                "https://a.com",
+                { "uri": "b.com", "match_type": 1 },
+            ]),
+        ),
+        file,
+    )
+    .unwrap();
+    assert_eq!(
+        test_uris(&db),
+        &[
+            Uri {
+                uri: "https://a.com".to_string(),
+                match_type: None,
+            },
+            Uri {
+                uri: "b.com".to_string(),
+                match_type: Some(crate::api::UriMatchType::Host),
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_version() {
+    let file = std::path::PathBuf::from("db.json");
+
+    let db = Db::from_json(
+        &test_db(Some(1), &serde_json::json!([{ "uri": "a.com" }])),
+        file.clone(),
+    )
+    .unwrap();
+    let json: serde_json::Value =
+        serde_json::from_str(&db.to_json().unwrap()).unwrap();
+    assert_eq!(json["version"], VERSION);
+    let reloaded = Db::from_json(&json.to_string(), file.clone()).unwrap();
+    assert_eq!(reloaded.entries, db.entries);
+    assert!(reloaded.pending.is_empty());
+
+    assert!(matches!(
+        Db::from_json(&test_db(Some(1000), &serde_json::json!([])), file),
+        Err(Error::LoadDbVersion { .. })
+    ));
+}
+
+#[test]
+fn test_queue() {
+    let entry = |id: &str, name: &str| {
+        let mut db = Db::from_json(
+            &test_db(None, &serde_json::json!([])),
+            std::path::PathBuf::from("db.json"),
+        )
+        .unwrap();
+        let mut entry = db.entries.remove(0);
+        entry.id = id.to_string();
+        entry.name = name.to_string();
+        entry
+    };
+
+    let mut db = Db::new();
+    db.entries = vec![entry("a", "a"), entry("b", "b")];
+
+    db.queue(PendingAction::Add {
+        entry: entry("c", "c"),
+    });
+    db.queue(PendingAction::Edit {
+        entry: entry("c", "c2"),
+        orig: entry("c", "c"),
+    });
+    db.queue(PendingAction::Edit {
+        entry: entry("a", "a2"),
+        orig: entry("a", "a"),
+    });
+    db.queue(PendingAction::Edit {
+        entry: entry("a", "a3"),
+        orig: entry("a", "a2"),
+    });
+    db.queue(PendingAction::Remove {
+        orig: entry("b", "b"),
+    });
+    assert_eq!(
+        db.pending
+            .iter()
+            .map(|change| change.action.clone())
+            .collect::<Vec<_>>(),
+        vec![
+            PendingAction::Add {
+                entry: entry("c", "c2"),
+            },
+            PendingAction::Edit {
+                entry: entry("a", "a3"),
+                orig: entry("a", "a"),
+            },
+            PendingAction::Remove {
+                orig: entry("b", "b"),
+            },
+        ]
+    );
+
+    db.apply_pending();
+    assert_eq!(db.entries, vec![entry("a", "a3"), entry("c", "c2")]);
+
+    db.queue(PendingAction::Remove {
+        orig: entry("c", "c2"),
+    });
+    assert_eq!(db.pending.len(), 2);
+}
+
+#[test]
+fn test_migrate_revision_dates() {
+    let mut db: serde_json::Value = serde_json::from_str(&test_db(
+        Some(2),
+        &serde_json::json!([{ "uri": "a.com", "match_type": null }]),
+    ))
+    .unwrap();
+    let entry = db["entries"][0].clone();
+    db["pending"] = serde_json::json!([{
+        "action": { "Edit": { "entry": entry, "orig": entry } },
+        "conflict": false,
+    }]);
+
+    let mut migrated = db.clone();
+    migrate_revision_dates(&mut migrated);
+    assert_eq!(
+        migrated["entries"][0]["revision_date"],
+        serde_json::Value::Null
+    );
+    assert_eq!(
+        migrated["pending"][0]["action"]["Edit"]["orig"]["revision_date"],
+        serde_json::Value::Null
+    );
+
+    let db =
+        Db::from_json(&db.to_string(), std::path::PathBuf::from("db.json"))
+            .unwrap();
+    assert_eq!(db.entries[0].revision_date, None);
+    assert_eq!(db.pending.len(), 1);
+}
@@ -47,6 +47,13 @@ pub fn db_file(server: &str, email: &str) -> std::path::PathBuf {
     cache_dir().join(format!("{server}:{email}.json"))
 }
 
+pub fn pin_file(server: &str, email: &str) -> std::path::PathBuf {
+    let server =
+        percent_encoding::percent_encode(server.as_bytes(), INVALID_PATH)
+            .to_string();
+    data_dir().join(format!("{server}:{email}.pin.json"))
+}
+
 pub fn pid_file() -> std::path::PathBuf {
     runtime_dir().join("pidfile")
 }
@@ -59,6 +66,10 @@ pub fn agent_stderr_file() -> std::path::PathBuf {
     data_dir().join("agent.err")
 }
 
+pub fn audit_log_file() -> std::path::PathBuf {
+    data_dir().join("audit.log")
+}
+
 pub fn device_id_file() -> std::path::PathBuf {
     data_dir().join("device_id")
 }
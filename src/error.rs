@@ -1,5 +1,8 @@
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[error("the entry was modified on the server since it was last synced")]
+    CipherOutOfDate,
+
     #[error("email address not set")]
     ConfigMissingEmail,
 
@@ -74,6 +77,12 @@ pub enum Error {
     #[error("invalid cipherstring: {reason}")]
     InvalidCipherString { reason: String },
 
+    #[error("invalid client policy: {policy}")]
+    InvalidClientPolicy { policy: String },
+
+    #[error("invalid prompt backend: {backend}")]
+    InvalidPromptBackend { backend: String },
+
     #[error(
         "invalid value for ${var}: {}",
         .editor.to_string_lossy()
@@ -86,6 +95,9 @@ pub enum Error {
     #[error("invalid mac")]
     InvalidMac,
 
+    #[error("unexpected key length {len} in session keyring")]
+    InvalidKeyringPayload { len: libc::c_long },
+
     #[error("invalid two factor provider type: {ty}")]
     InvalidTwoFactorProvider { ty: String },
 
@@ -94,6 +106,18 @@ pub enum Error {
         source: serde_path_to_error::Error<serde_json::Error>,
     },
 
+    #[error("failed to access the session keyring")]
+    Keyring { source: std::io::Error },
+
+    #[error("the session keyring is only supported on linux")]
+    KeyringUnsupported,
+
+    #[error("failed to load audit log from {}", .file.display())]
+    LoadAuditLog {
+        source: std::io::Error,
+        file: std::path::PathBuf,
+    },
+
     #[error("failed to load config from {}", .file.display())]
     LoadConfig {
         source: std::io::Error,
@@ -130,18 +154,52 @@ pub enum Error {
         file: std::path::PathBuf,
     },
 
+    #[error(
+        "failed to load db from {}: unsupported version {version}",
+        .file.display()
+    )]
+    LoadDbVersion {
+        version: String,
+        file: std::path::PathBuf,
+    },
+
     #[error("failed to load device id from {}", .file.display())]
     LoadDeviceId {
         source: tokio::io::Error,
         file: std::path::PathBuf,
     },
 
+    #[error("failed to load pin from {}", .file.display())]
+    LoadPin {
+        source: std::io::Error,
+        file: std::path::PathBuf,
+    },
+
+    #[error("failed to load pin from {}", .file.display())]
+    LoadPinJson {
+        source: serde_json::Error,
+        file: std::path::PathBuf,
+    },
+
     #[error("failed to load client cert from {}", .file.display())]
     LoadClientCert {
         source: tokio::io::Error,
         file: std::path::PathBuf,
     },
 
+    #[error("failed to lock db at {}", .file.display())]
+    LockDb {
+        source: std::io::Error,
+        file: std::path::PathBuf,
+    },
+
+    #[error(
+        "timed out waiting for the lock on db at {} (is a sync still in \
+        progress?)",
+        .file.display()
+    )]
+    LockDbTimeout { file: std::path::PathBuf },
+
     #[error("invalid padding")]
     Padding,
 
@@ -157,6 +215,9 @@ pub enum Error {
     #[error("failed to run argon2")]
     Argon2,
 
+    #[error("no terminal available to prompt on")]
+    NoTty,
+
     #[error("pinentry cancelled")]
     PinentryCancelled,
 
@@ -178,12 +239,24 @@ pub enum Error {
         file: std::path::PathBuf,
     },
 
+    #[error("failed to remove pin at {}", .file.display())]
+    RemovePin {
+        source: std::io::Error,
+        file: std::path::PathBuf,
+    },
+
     #[error("api request returned error: {status}")]
     RequestFailed { status: u16 },
 
     #[error("api request unauthorized")]
     RequestUnauthorized,
 
+    #[error("error running {program}")]
+    RunPrompt {
+        program: String,
+        source: std::io::Error,
+    },
+
     #[error("error making api request")]
     Reqwest { source: reqwest::Error },
 
@@ -193,6 +266,18 @@ pub enum Error {
     #[error("failed to decrypt")]
     RsaPkcs8 { source: rsa::pkcs8::Error },
 
+    #[error("failed to save audit log to {}", .file.display())]
+    SaveAuditLog {
+        source: tokio::io::Error,
+        file: std::path::PathBuf,
+    },
+
+    #[error("failed to save audit log to {}", .file.display())]
+    SaveAuditLogJson {
+        source: serde_json::Error,
+        file: std::path::PathBuf,
+    },
+
     #[error("failed to save config to {}", .file.display())]
     SaveConfig {
         source: std::io::Error,
@@ -212,13 +297,19 @@ pub enum Error {
     },
 
     #[error("failed to save db to {}", .file.display())]
-    SaveDbAsync {
-        source: tokio::io::Error,
+    SaveDbJson {
+        source: serde_json::Error,
         file: std::path::PathBuf,
     },
 
-    #[error("failed to save db to {}", .file.display())]
-    SaveDbJson {
+    #[error("failed to save pin to {}", .file.display())]
+    SavePin {
+        source: std::io::Error,
+        file: std::path::PathBuf,
+    },
+
+    #[error("failed to save pin to {}", .file.display())]
+    SavePinJson {
         source: serde_json::Error,
         file: std::path::PathBuf,
     },
@@ -235,6 +326,9 @@ pub enum Error {
         sso_email_2fa_session_token: Option<String>,
     },
 
+    #[error("error prompting on terminal")]
+    TtyIo { source: std::io::Error },
+
     #[error("unimplemented cipherstring type: {ty}")]
     UnimplementedCipherStringType { ty: String },
 
@@ -245,4 +339,16 @@ pub enum Error {
     InvalidKdfType { ty: String },
 }
 
+impl Error {
+    // whether the request failed because the server couldn't be reached at
+    // all, as opposed to the server rejecting it
+    pub fn is_offline(&self) -> bool {
+        matches!(
+            self,
+            Self::Reqwest { source }
+                if source.is_connect() || source.is_timeout()
+        )
+    }
+}
+
 pub type Result<T> = std::result::Result<T, Error>;
@@ -0,0 +1,217 @@
+// persists the unlocked vault key in the linux session keyring, so that a
+// restarted agent can pick up where the previous one left off without the
+// key ever being written to disk. the payload is the 64 byte vault key
+// followed by the unlock lifetime deadline (as big endian seconds since the
+// epoch, or 0 if there is none), so that restarting the agent can't be used
+// to extend the lifetime.
+
+use crate::prelude::*;
+
+#[cfg(target_os = "linux")]
+const KEY_TYPE: &std::ffi::CStr = c"user";
+#[cfg(target_os = "linux")]
+const KEYS_LEN: usize = 64;
+#[cfg(target_os = "linux")]
+const PAYLOAD_LEN: usize = KEYS_LEN + 8;
+
+pub struct Stored {
+    pub keys: crate::locked::Keys,
+    pub lifetime_deadline: Option<std::time::SystemTime>,
+}
+
+#[cfg(target_os = "linux")]
+fn description() -> std::ffi::CString {
+    // profile names come from an environment variable, so can't contain
+    // nul bytes
+    std::ffi::CString::new(format!("rbw:{}", crate::dirs::profile())).unwrap()
+}
+
+#[cfg(target_os = "linux")]
+pub fn store(
+    keys: &crate::locked::Keys,
+    lifetime_deadline: Option<std::time::SystemTime>,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    let deadline = lifetime_deadline
+        .and_then(|deadline| {
+            deadline.duration_since(std::time::UNIX_EPOCH).ok()
+        })
+        .map_or(0, |deadline| deadline.as_secs());
+    let mut payload = crate::locked::Vec::new();
+    payload.extend(keys.enc_key().iter().copied());
+    payload.extend(keys.mac_key().iter().copied());
+    payload.extend(deadline.to_be_bytes().into_iter());
+
+    let description = description();
+    // add_key replaces the payload of an existing key with the same
+    // description
+    // SAFETY: the key type and description are nul terminated CStrs which
+    // outlive the call, and the payload pointer and length both come from
+    // the same buffer, which the kernel only reads from
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_add_key,
+            KEY_TYPE.as_ptr(),
+            description.as_ptr(),
+            payload.data().as_ptr(),
+            payload.data().len(),
+            libc::KEY_SPEC_SESSION_KEYRING,
+        )
+    };
+    if serial < 0 {
+        return Err(last_os_error());
+    }
+    set_key_timeout(serial, timeout)
+}
+
+#[cfg(target_os = "linux")]
+pub fn load() -> Result<Option<Stored>> {
+    let Some(serial) = search()? else {
+        return Ok(None);
+    };
+
+    let mut payload = crate::locked::Vec::new();
+    payload.zero();
+    // SAFETY: payload has been filled to its full capacity by zero(), so
+    // the pointer is valid for writes of the length that we pass. the
+    // kernel never writes more than that, and returns the full size of the
+    // key (which is checked below) if it doesn't fit
+    let len = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_READ,
+            serial,
+            payload.data_mut().as_mut_ptr(),
+            payload.data().len(),
+        )
+    };
+    if len < 0 {
+        return Err(last_os_error());
+    }
+    if usize::try_from(len).ok() != Some(PAYLOAD_LEN) {
+        return Err(Error::InvalidKeyringPayload { len });
+    }
+    payload.truncate(PAYLOAD_LEN);
+
+    let deadline = u64::from_be_bytes(
+        payload.data()[KEYS_LEN..PAYLOAD_LEN].try_into().unwrap(),
+    );
+    let lifetime_deadline = (deadline > 0).then(|| {
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(deadline)
+    });
+    payload.truncate(KEYS_LEN);
+
+    Ok(Some(Stored {
+        keys: crate::locked::Keys::new(payload),
+        lifetime_deadline,
+    }))
+}
+
+#[cfg(target_os = "linux")]
+pub fn set_timeout(timeout: std::time::Duration) -> Result<()> {
+    if let Some(serial) = search()? {
+        set_key_timeout(serial, timeout)?;
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+pub fn remove() -> Result<()> {
+    let Some(serial) = search()? else {
+        return Ok(());
+    };
+    // SAFETY: KEYCTL_INVALIDATE only takes a key serial number, no pointers
+    let res = unsafe {
+        libc::syscall(libc::SYS_keyctl, libc::KEYCTL_INVALIDATE, serial)
+    };
+    if res < 0 {
+        return Err(last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn search() -> Result<Option<libc::c_long>> {
+    let description = description();
+    // SAFETY: the key type and description are nul terminated CStrs which
+    // outlive the call, and the destination keyring argument of 0 means
+    // that the found key isn't linked anywhere
+    let serial = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_SEARCH,
+            libc::KEY_SPEC_SESSION_KEYRING,
+            KEY_TYPE.as_ptr(),
+            description.as_ptr(),
+            0,
+        )
+    };
+    if serial < 0 {
+        let err = std::io::Error::last_os_error();
+        // expired and revoked keys are treated the same as missing ones
+        if matches!(
+            err.raw_os_error(),
+            Some(libc::ENOKEY | libc::EKEYEXPIRED | libc::EKEYREVOKED)
+        ) {
+            return Ok(None);
+        }
+        return Err(Error::Keyring { source: err });
+    }
+    Ok(Some(serial))
+}
+
+#[cfg(target_os = "linux")]
+fn last_os_error() -> Error {
+    Error::Keyring {
+        source: std::io::Error::last_os_error(),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn set_key_timeout(
+    serial: libc::c_long,
+    timeout: std::time::Duration,
+) -> Result<()> {
+    // a timeout of zero means that the key never expires, so always round
+    // up to at least one second
+    let timeout = libc::c_uint::try_from(timeout.as_secs().max(1))
+        .unwrap_or(libc::c_uint::MAX);
+    // SAFETY: KEYCTL_SET_TIMEOUT only takes a key serial number and a
+    // number of seconds, no pointers
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_keyctl,
+            libc::KEYCTL_SET_TIMEOUT,
+            serial,
+            timeout,
+        )
+    };
+    if res < 0 {
+        return Err(last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn store(
+    _keys: &crate::locked::Keys,
+    _lifetime_deadline: Option<std::time::SystemTime>,
+    _timeout: std::time::Duration,
+) -> Result<()> {
+    Err(Error::KeyringUnsupported)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn load() -> Result<Option<Stored>> {
+    Ok(None)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_timeout(_timeout: std::time::Duration) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn remove() -> Result<()> {
+    Ok(())
+}
@@ -1,5 +1,6 @@
 pub mod actions;
 pub mod api;
+pub mod audit;
 pub mod base64;
 pub mod cipherstring;
 pub mod config;
@@ -9,9 +10,13 @@ pub mod edit;
 pub mod error;
 pub mod identity;
 pub mod json;
+pub mod keyring;
 pub mod locked;
+pub mod pin;
 pub mod pinentry;
 mod prelude;
+pub mod prompt;
 pub mod protocol;
 pub mod pwgen;
+pub mod tty;
 pub mod wordlist;
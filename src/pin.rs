@@ -0,0 +1,195 @@
+use crate::prelude::*;
+
+use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+// after this many incorrect attempts, the pin is forgotten and the master
+// password is required again. note that this is only enforced by the agent -
+// the pin is still protected by the account's kdf, but short pins can't
+// withstand an offline attack against the stored file.
+pub const MAX_ATTEMPTS: u32 = 3;
+
+// the user's vault key, encrypted with a key derived from a short pin, so
+// that the vault can be unlocked without typing the full master password
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct Pin {
+    pub protected_key: String,
+    pub failed_attempts: u32,
+}
+
+impl Pin {
+    pub fn new(
+        email: &str,
+        pin: &crate::locked::Password,
+        kdf: crate::api::KdfType,
+        iterations: u32,
+        memory: Option<u32>,
+        parallelism: Option<u32>,
+        keys: &crate::locked::Keys,
+    ) -> Result<Self> {
+        let identity = crate::identity::Identity::new(
+            email,
+            pin,
+            kdf,
+            iterations,
+            memory,
+            parallelism,
+        )?;
+
+        let mut plaintext = crate::locked::Vec::new();
+        plaintext.extend(keys.enc_key().iter().copied());
+        plaintext.extend(keys.mac_key().iter().copied());
+        let protected_key =
+            crate::cipherstring::CipherString::encrypt_symmetric(
+                &identity.keys,
+                plaintext.data(),
+            )?;
+
+        Ok(Self {
+            protected_key: protected_key.to_string(),
+            failed_attempts: 0,
+        })
+    }
+
+    pub fn decrypt_key(
+        &self,
+        email: &str,
+        pin: &crate::locked::Password,
+        kdf: crate::api::KdfType,
+        iterations: u32,
+        memory: Option<u32>,
+        parallelism: Option<u32>,
+    ) -> Result<crate::locked::Keys> {
+        let identity = crate::identity::Identity::new(
+            email,
+            pin,
+            kdf,
+            iterations,
+            memory,
+            parallelism,
+        )?;
+
+        let protected_key =
+            crate::cipherstring::CipherString::new(&self.protected_key)?;
+        match protected_key.decrypt_locked_symmetric(&identity.keys) {
+            Ok(keys) => Ok(crate::locked::Keys::new(keys)),
+            Err(Error::InvalidMac) => Err(Error::IncorrectPassword {
+                message: "PIN is incorrect. Try again.".to_string(),
+            }),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn attempts_remaining(&self) -> u32 {
+        MAX_ATTEMPTS.saturating_sub(self.failed_attempts)
+    }
+
+    pub async fn load_async(
+        server: &str,
+        email: &str,
+    ) -> Result<Option<Self>> {
+        let file = crate::dirs::pin_file(server, email);
+        let mut fh = match tokio::fs::File::open(&file).await {
+            Ok(fh) => fh,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(source) => return Err(Error::LoadPin { source, file }),
+        };
+        let mut json = String::new();
+        fh.read_to_string(&mut json).await.map_err(|source| {
+            Error::LoadPin {
+                source,
+                file: file.clone(),
+            }
+        })?;
+        let slf: Self = serde_json::from_str(&json)
+            .map_err(|source| Error::LoadPinJson { source, file })?;
+        Ok(Some(slf))
+    }
+
+    pub async fn save_async(&self, server: &str, email: &str) -> Result<()> {
+        let file = crate::dirs::pin_file(server, email);
+        let mut fh = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&file)
+            .await
+            .map_err(|source| Error::SavePin {
+                source,
+                file: file.clone(),
+            })?;
+        fh.write_all(
+            serde_json::to_string(self)
+                .map_err(|source| Error::SavePinJson {
+                    source,
+                    file: file.clone(),
+                })?
+                .as_bytes(),
+        )
+        .await
+        .map_err(|source| Error::SavePin { source, file })?;
+        Ok(())
+    }
+
+    pub fn remove(server: &str, email: &str) -> Result<()> {
+        let file = crate::dirs::pin_file(server, email);
+        let res = std::fs::remove_file(&file);
+        if let Err(e) = &res {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                return Ok(());
+            }
+        }
+        res.map_err(|source| Error::RemovePin { source, file })?;
+        Ok(())
+    }
+}
+
+#[test]
+fn test_pin() {
+    fn password(s: &str) -> crate::locked::Password {
+        let mut v = crate::locked::Vec::new();
+        v.extend(s.bytes());
+        crate::locked::Password::new(v)
+    }
+
+    let mut keys = crate::locked::Vec::new();
+    keys.extend(0..64);
+    let keys = crate::locked::Keys::new(keys);
+
+    let pin = Pin::new(
+        "a@b.c",
+        &password("1234"),
+        crate::api::KdfType::Pbkdf2,
+        1000,
+        None,
+        None,
+        &keys,
+    )
+    .unwrap();
+    let decrypted = pin
+        .decrypt_key(
+            "a@b.c",
+            &password("1234"),
+            crate::api::KdfType::Pbkdf2,
+            1000,
+            None,
+            None,
+        )
+        .unwrap();
+    assert_eq!(decrypted.enc_key(), keys.enc_key());
+    assert_eq!(decrypted.mac_key(), keys.mac_key());
+
+    assert!(matches!(
+        pin.decrypt_key(
+            "a@b.c",
+            &password("4321"),
+            crate::api::KdfType::Pbkdf2,
+            1000,
+            None,
+            None,
+        ),
+        Err(Error::IncorrectPassword { .. })
+    ));
+}
@@ -12,39 +12,8 @@ pub async fn getpin(
     environment: &crate::protocol::Environment,
     grab: bool,
 ) -> Result<crate::locked::Password> {
-    let mut opts = tokio::process::Command::new(pinentry);
-    opts.stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped());
-    let mut args = vec!["--timeout".into(), "0".into()];
-    if let Some(tty) = environment.tty() {
-        args.extend(["--ttyname".into(), tty.into()]);
-    }
-
-    let env_vars = environment.env_vars();
-    // Not all pinentry appear to respect the --display flag, so we also keep the environment
-    // variable.
-    if let Some(display) =
-        env_vars.get(std::ffi::OsString::from("DISPLAY").as_os_str())
-    {
-        args.extend(["--display".into(), display.clone()]);
-    }
-    if !grab {
-        args.push("--no-global-grab".into());
-    }
-    opts.args(args);
-
-    for env_var in &*crate::protocol::ENVIRONMENT_VARIABLES_OS {
-        if let Some(val) = env_vars.get(env_var) {
-            opts.env(env_var, val);
-        } else {
-            opts.env_remove(env_var);
-        }
-    }
-    opts.envs(env_vars);
-
-    let mut child = opts.spawn().map_err(|source| Error::Spawn { source })?;
-    // unwrap is safe because we specified stdin as piped in the command opts
-    // above
+    let mut child = spawn(pinentry, environment, grab)?;
+    // unwrap is safe because spawn() sets stdin to piped
     let mut stdin = child.stdin.take().unwrap();
 
     let mut ncommands = 1;
@@ -54,18 +23,18 @@ pub async fn getpin(
         .map_err(|source| Error::WriteStdin { source })?;
     ncommands += 1;
     stdin
-        .write_all(format!("SETPROMPT {prompt}\n").as_bytes())
+        .write_all(command("SETPROMPT", prompt).as_bytes())
         .await
         .map_err(|source| Error::WriteStdin { source })?;
     ncommands += 1;
     stdin
-        .write_all(format!("SETDESC {desc}\n").as_bytes())
+        .write_all(command("SETDESC", desc).as_bytes())
         .await
         .map_err(|source| Error::WriteStdin { source })?;
     ncommands += 1;
     if let Some(err) = err {
         stdin
-            .write_all(format!("SETERROR {err}\n").as_bytes())
+            .write_all(command("SETERROR", err).as_bytes())
             .await
             .map_err(|source| Error::WriteStdin { source })?;
         ncommands += 1;
@@ -79,8 +48,7 @@ pub async fn getpin(
 
     let mut buf = crate::locked::Vec::new();
     buf.zero();
-    // unwrap is safe because we specified stdout as piped in the command opts
-    // above
+    // unwrap is safe because spawn() sets stdout to piped
     let len = read_password(
         ncommands,
         buf.data_mut(),
@@ -97,6 +65,114 @@ pub async fn getpin(
     Ok(crate::locked::Password::new(buf))
 }
 
+// asks the user a yes/no question, returning whether they confirmed it
+pub async fn confirm(
+    pinentry: &str,
+    desc: &str,
+    ok: &str,
+    cancel: &str,
+    environment: &crate::protocol::Environment,
+) -> Result<bool> {
+    let mut child = spawn(pinentry, environment, true)?;
+    // unwrap is safe because spawn() sets stdin to piped
+    let mut stdin = child.stdin.take().unwrap();
+
+    let mut ncommands = 1;
+    for command in confirm_commands(desc, ok, cancel) {
+        stdin
+            .write_all(command.as_bytes())
+            .await
+            .map_err(|source| Error::WriteStdin { source })?;
+        ncommands += 1;
+    }
+    drop(stdin);
+
+    let mut buf = [0; 1024];
+    // unwrap is safe because spawn() sets stdout to piped
+    let res =
+        read_password(ncommands, &mut buf, child.stdout.as_mut().unwrap())
+            .await;
+
+    child
+        .wait()
+        .await
+        .map_err(|source| Error::PinentryWait { source })?;
+
+    match res {
+        Ok(_) => Ok(true),
+        Err(Error::PinentryCancelled) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn confirm_commands(desc: &str, ok: &str, cancel: &str) -> Vec<String> {
+    vec![
+        "SETTITLE rbw\n".to_string(),
+        command("SETDESC", desc),
+        command("SETOK", ok),
+        command("SETCANCEL", cancel),
+        "CONFIRM\n".to_string(),
+    ]
+}
+
+fn command(name: &str, arg: &str) -> String {
+    format!("{name} {}\n", escape(arg))
+}
+
+// assuan commands are terminated by newlines, so arguments need to be
+// percent-encoded. otherwise, text that we don't control (like the username
+// in an ssh signing request) could inject additional commands.
+pub fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '%' => escaped.push_str("%25"),
+            '\r' => escaped.push_str("%0D"),
+            '\n' => escaped.push_str("%0A"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn spawn(
+    pinentry: &str,
+    environment: &crate::protocol::Environment,
+    grab: bool,
+) -> Result<tokio::process::Child> {
+    let mut opts = tokio::process::Command::new(pinentry);
+    opts.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped());
+    let mut args = vec!["--timeout".into(), "0".into()];
+    if let Some(tty) = environment.tty() {
+        args.extend(["--ttyname".into(), tty.into()]);
+    }
+
+    let env_vars = environment.env_vars();
+    // Not all pinentry appear to respect the --display flag, so we also keep the environment
+    // variable.
+    if let Some(display) =
+        env_vars.get(std::ffi::OsString::from("DISPLAY").as_os_str())
+    {
+        args.extend(["--display".into(), display.clone()]);
+    }
+    if !grab {
+        args.push("--no-global-grab".into());
+    }
+    opts.args(args);
+
+    for env_var in &*crate::protocol::ENVIRONMENT_VARIABLES_OS {
+        if let Some(val) = env_vars.get(env_var) {
+            opts.env(env_var, val);
+        } else {
+            opts.env_remove(env_var);
+        }
+    }
+    opts.envs(env_vars);
+
+    opts.spawn().map_err(|source| Error::Spawn { source })
+}
+
 async fn read_password<R>(
     mut ncommands: u8,
     data: &mut [u8],
@@ -131,7 +207,8 @@ where
                 let _ = split.next(); // ERR
                 let code = split.next();
                 match code {
-                    Some("83886179") => {
+                    // operation cancelled, or not confirmed (for CONFIRM)
+                    Some("83886179" | "83886194") => {
                         return Err(Error::PinentryCancelled);
                     }
                     Some(code) => {
@@ -249,3 +326,39 @@ fn test_read_password() {
         });
     }
 }
+
+#[test]
+fn test_read_password_cancelled() {
+    let cancel_inputs = &[
+        &b"OK\nOK\nERR 83886179 Operation cancelled <Pinentry>\n"[..],
+        &b"OK\nOK\nERR 83886194 Not confirmed <Pinentry>\n"[..],
+    ];
+
+    for input in cancel_inputs {
+        let mut buf = [0; 64];
+        tokio::runtime::Runtime::new().unwrap().block_on(async {
+            let res = read_password(4, &mut buf, &input[..]).await;
+            assert!(matches!(res, Err(Error::PinentryCancelled)));
+        });
+    }
+}
+
+#[test]
+fn test_escape() {
+    assert_eq!(escape("100%\r\ndone"), "100%25%0D%0Adone");
+
+    // a username like this one comes from the remote side of a forwarded
+    // ssh agent connection
+    let desc = "Allow use of the ssh key 'id' to log in as 'git\nSETDESC \
+        Allow use of the ssh key 'id' to log in as 'git'?'?";
+    let commands = confirm_commands(desc, "Allow", "Deny");
+    for command in &commands {
+        assert_eq!(command.matches('\n').count(), 1);
+        assert!(command.ends_with('\n'));
+    }
+    assert_eq!(
+        commands[1],
+        "SETDESC Allow use of the ssh key 'id' to log in as \
+        'git%0ASETDESC Allow use of the ssh key 'id' to log in as 'git'?'?\n"
+    );
+}
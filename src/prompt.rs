@@ -0,0 +1,337 @@
+use crate::prelude::*;
+
+use tokio::io::AsyncReadExt as _;
+
+const DEFAULT_ASKPASS: &str = "ssh-askpass";
+const SYSTEMD_ASK_PASSWORD: &str = "systemd-ask-password";
+
+// the different ways that we can ask the user for passwords and
+// confirmations
+#[derive(Debug, Clone)]
+pub enum Backend {
+    // an assuan pinentry program (falling back to the terminal if it isn't
+    // installed)
+    Pinentry { program: String },
+    // prompting directly on the client's terminal
+    Tty,
+    // an ssh-askpass style program, which takes the prompt as its only
+    // argument and prints the password to stdout. if no program is
+    // configured, SSH_ASKPASS from the agent's own environment is used (the
+    // client's environment can't be trusted to choose what the agent runs).
+    Askpass { program: Option<String> },
+    // systemd's password agent protocol, via systemd-ask-password
+    Systemd,
+}
+
+impl Backend {
+    pub fn new(config: &crate::config::Config) -> Self {
+        match config.prompt {
+            crate::config::PromptBackend::Pinentry => Self::Pinentry {
+                program: config.pinentry.clone(),
+            },
+            crate::config::PromptBackend::Tty => Self::Tty,
+            crate::config::PromptBackend::Askpass => Self::Askpass {
+                program: config.askpass.clone(),
+            },
+            crate::config::PromptBackend::Systemd => Self::Systemd,
+        }
+    }
+
+    pub async fn getpin(
+        &self,
+        prompt: &str,
+        desc: &str,
+        err: Option<&str>,
+        environment: &crate::protocol::Environment,
+        grab: bool,
+    ) -> Result<crate::locked::Password> {
+        match self {
+            Self::Pinentry { program } => {
+                match crate::pinentry::getpin(
+                    program,
+                    prompt,
+                    desc,
+                    err,
+                    environment,
+                    grab,
+                )
+                .await
+                {
+                    Err(e)
+                        if is_missing(&e) && environment.tty().is_some() =>
+                    {
+                        log::warn!(
+                            "{program} not found, prompting on the terminal \
+                            instead"
+                        );
+                        crate::tty::getpin(prompt, desc, err, environment)
+                            .await
+                    }
+                    res => res,
+                }
+            }
+            Self::Tty => {
+                crate::tty::getpin(prompt, desc, err, environment).await
+            }
+            Self::Askpass { .. } | Self::Systemd => {
+                let mut buf = crate::locked::Vec::new();
+                buf.zero();
+                let len = self
+                    .run(
+                        &message(prompt, desc, err),
+                        false,
+                        buf.data_mut(),
+                        environment,
+                    )
+                    .await?;
+                buf.truncate(len);
+                Ok(crate::locked::Password::new(buf))
+            }
+        }
+    }
+
+    // asks the user a yes/no question, returning whether they confirmed it
+    pub async fn confirm(
+        &self,
+        desc: &str,
+        ok: &str,
+        cancel: &str,
+        environment: &crate::protocol::Environment,
+    ) -> Result<bool> {
+        match self {
+            Self::Pinentry { program } => {
+                match crate::pinentry::confirm(
+                    program,
+                    desc,
+                    ok,
+                    cancel,
+                    environment,
+                )
+                .await
+                {
+                    Err(e)
+                        if is_missing(&e) && environment.tty().is_some() =>
+                    {
+                        log::warn!(
+                            "{program} not found, prompting on the terminal \
+                            instead"
+                        );
+                        crate::tty::confirm(desc, ok, cancel, environment)
+                            .await
+                    }
+                    res => res,
+                }
+            }
+            Self::Tty => {
+                crate::tty::confirm(desc, ok, cancel, environment).await
+            }
+            // askpass programs show a yes/no dialog when SSH_ASKPASS_PROMPT
+            // is set to confirm, and exit successfully if the user agreed
+            Self::Askpass { .. } => {
+                let mut buf = [0; 1024];
+                match self.run(desc, true, &mut buf, environment).await {
+                    Ok(_) => Ok(true),
+                    Err(Error::PinentryCancelled) => Ok(false),
+                    Err(e) => Err(e),
+                }
+            }
+            // systemd has no concept of a confirmation prompt, so ask the
+            // user to type out their answer instead
+            Self::Systemd => {
+                let mut buf = [0; 1024];
+                let len = match self
+                    .run(
+                        &format!("{desc} Type '{ok}' to confirm:"),
+                        true,
+                        &mut buf,
+                        environment,
+                    )
+                    .await
+                {
+                    Ok(len) => len,
+                    Err(Error::PinentryCancelled) => return Ok(false),
+                    Err(e) => return Err(e),
+                };
+                Ok(std::str::from_utf8(&buf[..len]).is_ok_and(|answer| {
+                    answer.trim().eq_ignore_ascii_case(ok)
+                }))
+            }
+        }
+    }
+
+    // runs an askpass style program, returning the length of its output
+    // (without the trailing newline) stored in data. a non-zero exit status
+    // is treated as the user cancelling the prompt.
+    async fn run(
+        &self,
+        message: &str,
+        confirm: bool,
+        data: &mut [u8],
+        environment: &crate::protocol::Environment,
+    ) -> Result<usize> {
+        let env_vars = environment.env_vars();
+        let (program, args) = match self {
+            Self::Askpass { program } => {
+                let program = program
+                    .clone()
+                    .or_else(|| std::env::var("SSH_ASKPASS").ok())
+                    .unwrap_or_else(|| DEFAULT_ASKPASS.to_string());
+                (program, vec![message.to_string()])
+            }
+            Self::Systemd => {
+                let mut args = vec![
+                    "--timeout=0".to_string(),
+                    format!("--id=rbw:{}", crate::dirs::profile()),
+                    "--icon=dialog-password".to_string(),
+                ];
+                if confirm {
+                    args.push("--echo=yes".to_string());
+                }
+                // system-wide password agents only accept requests from
+                // root
+                if !rustix::process::getuid().is_root() {
+                    args.push("--user".to_string());
+                }
+                args.push(message.to_string());
+                (SYSTEMD_ASK_PASSWORD.to_string(), args)
+            }
+            Self::Pinentry { .. } | Self::Tty => unreachable!(),
+        };
+
+        let mut opts = tokio::process::Command::new(&program);
+        opts.args(args)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::piped());
+        for env_var in &*crate::protocol::ENVIRONMENT_VARIABLES_OS {
+            if let Some(val) = env_vars.get(env_var) {
+                opts.env(env_var, val);
+            } else {
+                opts.env_remove(env_var);
+            }
+        }
+        if confirm {
+            opts.env("SSH_ASKPASS_PROMPT", "confirm");
+        }
+        let mut child = opts.spawn().map_err(|source| Error::RunPrompt {
+            program: program.clone(),
+            source,
+        })?;
+
+        // unwrap is safe because we set stdout to piped
+        let mut stdout = child.stdout.take().unwrap();
+        let mut len = 0;
+        loop {
+            if len >= data.len() {
+                return Err(Error::RunPrompt {
+                    program,
+                    source: std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        "output too long",
+                    ),
+                });
+            }
+            let bytes =
+                stdout.read(&mut data[len..]).await.map_err(|source| {
+                    Error::RunPrompt {
+                        program: program.clone(),
+                        source,
+                    }
+                })?;
+            if bytes == 0 {
+                break;
+            }
+            len += bytes;
+        }
+        let status =
+            child.wait().await.map_err(|source| Error::RunPrompt {
+                program: program.clone(),
+                source,
+            })?;
+        if !status.success() {
+            return Err(Error::PinentryCancelled);
+        }
+
+        if data[..len].ends_with(b"\n") {
+            len -= 1;
+        }
+        Ok(len)
+    }
+}
+
+// askpass programs only take a single line of text, so combine everything
+// that pinentry would display separately
+fn message(prompt: &str, desc: &str, err: Option<&str>) -> String {
+    let prompt = prompt.replace("__", "_");
+    err.map_or_else(
+        || format!("{desc}. {prompt}:"),
+        |err| format!("{err}. {desc}. {prompt}:"),
+    )
+}
+
+fn is_missing(e: &Error) -> bool {
+    matches!(
+        e,
+        Error::Spawn { source }
+            if source.kind() == std::io::ErrorKind::NotFound
+    )
+}
+
+#[test]
+fn test_message() {
+    assert_eq!(
+        message(
+            "Master Password",
+            "Unlock the local database for 'rbw'",
+            None
+        ),
+        "Unlock the local database for 'rbw'. Master Password:"
+    );
+    assert_eq!(
+        message(
+            "API key client__id",
+            "Log in to api.bitwarden.com",
+            Some("incorrect api key (attempt 2/3)")
+        ),
+        "incorrect api key (attempt 2/3). Log in to api.bitwarden.com. API \
+        key client_id:"
+    );
+}
+
+#[test]
+fn test_askpass() {
+    let environment = crate::protocol::Environment::default();
+    tokio::runtime::Runtime::new().unwrap().block_on(async {
+        let backend = Backend::Askpass {
+            program: Some("echo".to_string()),
+        };
+        let password = backend
+            .getpin("Master Password", "Unlock", None, &environment, false)
+            .await
+            .unwrap();
+        assert_eq!(password.password(), b"Unlock. Master Password:");
+        assert!(backend
+            .confirm("Allow?", "Allow", "Deny", &environment)
+            .await
+            .unwrap());
+
+        let backend = Backend::Askpass {
+            program: Some("false".to_string()),
+        };
+        assert!(matches!(
+            backend
+                .getpin(
+                    "Master Password",
+                    "Unlock",
+                    None,
+                    &environment,
+                    false
+                )
+                .await,
+            Err(Error::PinentryCancelled)
+        ));
+        assert!(!backend
+            .confirm("Allow?", "Allow", "Deny", &environment)
+            .await
+            .unwrap());
+    });
+}
@@ -171,6 +171,7 @@ pub enum Action {
     Login,
     Register,
     Unlock,
+    SetPin,
     CheckLock,
     Lock,
     Sync,
@@ -186,10 +187,31 @@ pub enum Action {
     ClipboardStore {
         text: String,
     },
+    Index,
     Quit,
     Version,
 }
 
+impl Action {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Login => "login",
+            Self::Register => "register",
+            Self::Unlock => "unlock",
+            Self::SetPin => "set_pin",
+            Self::CheckLock => "check_lock",
+            Self::Lock => "lock",
+            Self::Sync => "sync",
+            Self::Decrypt { .. } => "decrypt",
+            Self::Encrypt { .. } => "encrypt",
+            Self::ClipboardStore { .. } => "clipboard_store",
+            Self::Index => "index",
+            Self::Quit => "quit",
+            Self::Version => "version",
+        }
+    }
+}
+
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
 #[serde(tag = "type")]
 pub enum Response {
@@ -197,5 +219,29 @@ pub enum Response {
     Error { error: String },
     Decrypt { plaintext: String },
     Encrypt { cipherstring: String },
+    Index { entries: Vec<IndexEntry> },
     Version { version: u32 },
 }
+
+// the decrypted metadata for an entry, as kept in the agent's search index.
+// this never includes passwords, totp secrets, or any other values which
+// could be protected by master password reprompt.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct IndexEntry {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub entry_type: String,
+    pub folder: Option<String>,
+    pub name: String,
+    pub user: Option<String>,
+    pub uris: Vec<(String, Option<crate::api::UriMatchType>)>,
+    pub fields: Vec<IndexField>,
+    pub notes: Option<String>,
+}
+
+// values of hidden fields are left out
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+pub struct IndexField {
+    pub name: Option<String>,
+    pub value: Option<String>,
+}
@@ -0,0 +1,203 @@
+use crate::prelude::*;
+
+use std::io::{Read as _, Write as _};
+use std::os::unix::fs::OpenOptionsExt as _;
+use zeroize::Zeroize as _;
+
+// prompts for a password directly on the client's terminal, for systems
+// without a pinentry program installed. the terminal is put into
+// non-canonical mode with echo disabled, so that we can handle line editing
+// and ^C ourselves (the agent isn't in the terminal's foreground process
+// group, so it wouldn't receive any signals).
+pub async fn getpin(
+    prompt: &str,
+    desc: &str,
+    err: Option<&str>,
+    environment: &crate::protocol::Environment,
+) -> Result<crate::locked::Password> {
+    let tty = environment.tty().ok_or(Error::NoTty)?.to_os_string();
+    // pinentry uses __ to escape underscores (since a single underscore
+    // marks a mnemonic)
+    let mut message = String::new();
+    if let Some(err) = err {
+        message.push_str(err);
+        message.push('\n');
+    }
+    message.push_str(desc);
+    message.push('\n');
+    message.push_str(&prompt.replace("__", "_"));
+    message.push_str(": ");
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = open(&tty)?;
+        file.write_all(message.as_bytes())
+            .map_err(|source| Error::TtyIo { source })?;
+        with_raw_mode(&file, read_password)
+    })
+    .await
+    .unwrap()
+}
+
+// asks the user a yes/no question on the client's terminal
+pub async fn confirm(
+    desc: &str,
+    ok: &str,
+    cancel: &str,
+    environment: &crate::protocol::Environment,
+) -> Result<bool> {
+    let tty = environment.tty().ok_or(Error::NoTty)?.to_os_string();
+    let message = format!("{desc}\n{ok} (y) / {cancel} (n)? ");
+
+    tokio::task::spawn_blocking(move || {
+        let mut file = open(&tty)?;
+        file.write_all(message.as_bytes())
+            .map_err(|source| Error::TtyIo { source })?;
+        let mut answer = String::new();
+        let mut c = [0; 1];
+        loop {
+            let bytes = file
+                .read(&mut c)
+                .map_err(|source| Error::TtyIo { source })?;
+            if bytes == 0 || c[0] == b'\n' {
+                break;
+            }
+            answer.push(char::from(c[0]));
+        }
+        Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+    })
+    .await
+    .unwrap()
+}
+
+// reads a secret from the current process's own terminal, without echoing
+// it
+pub fn read_secret(prompt: &str) -> Result<crate::locked::Password> {
+    let mut file = open(std::ffi::OsStr::new("/dev/tty"))?;
+    file.write_all(prompt.as_bytes())
+        .map_err(|source| Error::TtyIo { source })?;
+    with_raw_mode(&file, read_password)
+}
+
+fn open(tty: &std::ffi::OsStr) -> Result<std::fs::File> {
+    std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(tty)
+        .map_err(|source| Error::TtyIo { source })
+}
+
+fn with_raw_mode<T>(
+    file: &std::fs::File,
+    f: impl FnOnce(&std::fs::File) -> Result<T>,
+) -> Result<T> {
+    let orig = rustix::termios::tcgetattr(file)
+        .map_err(|e| Error::TtyIo { source: e.into() })?;
+    let mut raw = orig.clone();
+    raw.local_modes.remove(
+        rustix::termios::LocalModes::ECHO
+            | rustix::termios::LocalModes::ICANON
+            | rustix::termios::LocalModes::ISIG,
+    );
+    raw.special_codes[rustix::termios::SpecialCodeIndex::VMIN] = 1;
+    raw.special_codes[rustix::termios::SpecialCodeIndex::VTIME] = 0;
+    rustix::termios::tcsetattr(
+        file,
+        rustix::termios::OptionalActions::Flush,
+        &raw,
+    )
+    .map_err(|e| Error::TtyIo { source: e.into() })?;
+
+    let res = f(file);
+
+    // always restore the terminal, even if reading failed
+    let restored = rustix::termios::tcsetattr(
+        file,
+        rustix::termios::OptionalActions::Flush,
+        &orig,
+    )
+    .map_err(|e| Error::TtyIo { source: e.into() });
+    let mut file = file;
+    let _ = file.write_all(b"\n");
+    let res = res?;
+    restored?;
+    Ok(res)
+}
+
+fn read_password(file: &std::fs::File) -> Result<crate::locked::Password> {
+    let mut buf = crate::locked::Vec::new();
+    buf.zero();
+    let len = read_line(file, buf.data_mut())?;
+    buf.truncate(len);
+    Ok(crate::locked::Password::new(buf))
+}
+
+// reads a line of input in non-canonical mode, handling the basic line
+// editing keys that the terminal would otherwise handle for us
+fn read_line(mut r: impl std::io::Read, data: &mut [u8]) -> Result<usize> {
+    let mut len: usize = 0;
+    let mut c = [0; 1];
+    loop {
+        let bytes =
+            r.read(&mut c).map_err(|source| Error::TtyIo { source })?;
+        if bytes == 0 {
+            if len == 0 {
+                return Err(Error::PinentryCancelled);
+            }
+            break;
+        }
+        match c[0] {
+            b'\r' | b'\n' => break,
+            // ^C, or ^D on an empty line
+            0x03 => return Err(Error::PinentryCancelled),
+            0x04 if len == 0 => return Err(Error::PinentryCancelled),
+            // backspace
+            0x08 | 0x7f => len = len.saturating_sub(1),
+            // ^U
+            0x15 => len = 0,
+            c => {
+                if len >= data.len() {
+                    return Err(Error::TtyIo {
+                        source: std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            "password too long",
+                        ),
+                    });
+                }
+                data[len] = c;
+                len += 1;
+            }
+        }
+    }
+    // don't leave the last character of the password lying around
+    c.zeroize();
+    Ok(len)
+}
+
+#[test]
+fn test_read_line() {
+    let inputs = &[
+        (
+            &b"super secret password\n"[..],
+            &b"super secret password"[..],
+        ),
+        (&b"password\r"[..], &b"password"[..]),
+        (&b"passw\x7f\x7fword\n"[..], &b"pasword"[..]),
+        (&b"wrong\x15password\n"[..], &b"password"[..]),
+        (&b"\n"[..], &b""[..]),
+        (&b"password"[..], &b"password"[..]),
+    ];
+    for (input, output) in inputs {
+        let mut buf = [0; 64];
+        let len = read_line(&input[..], &mut buf).unwrap();
+        assert_eq!(&buf[..len], &output[..]);
+    }
+
+    for input in [&b"pass\x03"[..], &b"\x04"[..], &b""[..]] {
+        let mut buf = [0; 64];
+        assert!(matches!(
+            read_line(input, &mut buf),
+            Err(Error::PinentryCancelled)
+        ));
+    }
+}